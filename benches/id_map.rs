@@ -0,0 +1,67 @@
+//! IdCode to signal index lookups, as done for every value change by
+//! `collect_values`, on a synthetic trace of 1M value changes.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use vcd::Command::ChangeScalar;
+use vcd::Parser;
+use vcd_sync::{IdCode, IdMap, SignalsCode};
+
+const SIGNALS : u32 = 1000;
+const EVENTS : u32 = 1_000_000;
+
+fn trace() -> String
+{
+    let mut trace = String::from("$timescale 1ns $end\n$scope module top $end\n");
+    let mut id = IdCode::FIRST;
+    for i in 0..SIGNALS
+    {
+        trace.push_str(&format!("$var wire 1 {} s{} $end\n", id, i));
+        id = id.next();
+    }
+    trace.push_str("$upscope $end\n$enddefinitions $end\n");
+    for event in 0..EVENTS
+    {
+        if event % SIGNALS == 0
+        {
+            trace.push_str(&format!("#{}\n", event / SIGNALS));
+        }
+        // Spread the changes so that consecutive lookups hit different codes
+        trace.push_str(&format!("{}{}\n", event % 2, IdCode::from(event * 7919 % SIGNALS)));
+    }
+    trace
+}
+
+fn lookups(c : &mut Criterion)
+{
+    let mut parser = Parser::new(Cursor::new(trace()));
+    let header = parser.parse_header().unwrap();
+    let signals : SignalsCode = vcd_sync::signals(&header.items);
+    let events : Vec<IdCode> = parser
+        .filter_map(|cmd| match cmd.unwrap()
+        {
+            ChangeScalar(id, _) => Some(id),
+            _ => None,
+        })
+        .collect();
+
+    let hash_map : HashMap<IdCode, u32> = signals.iter().enumerate().map(|(i, (_, id))| (*id, i as u32)).collect();
+    let id_map = IdMap::new(&signals);
+
+    let mut group = c.benchmark_group("id_lookup_1m_events");
+    group.bench_function("hash_map", |b| b.iter(||
+    {
+        events.iter().filter_map(|id| hash_map.get(black_box(id))).map(|index| *index as u64).sum::<u64>()
+    }));
+    group.bench_function("id_map", |b| b.iter(||
+    {
+        events.iter().filter_map(|id| id_map.get(black_box(*id))).map(|index| index as u64).sum::<u64>()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
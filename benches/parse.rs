@@ -0,0 +1,90 @@
+//! Wall time of parsing two input traces serially and in parallel.
+//!
+//! The traces are generated once in the temp directory, their size defaults
+//! to 100MB each and can be changed with `VCD_SYNC_BENCH_MB`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use vcd_sync::{ParseOptions, VCD};
+
+const SIGNALS : usize = 64;
+
+fn generate(path : &PathBuf, size_bytes : u64) -> std::io::Result<()>
+{
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "$timescale 1ns $end")?;
+    writeln!(out, "$scope module top $end")?;
+    writeln!(out, "$var wire 1 ! reset $end")?;
+    for i in 0..SIGNALS
+    {
+        writeln!(out, "$var wire 8 s{} data{} $end", i, i)?;
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+    writeln!(out, "#0\n0!")?;
+
+    let mut written = 0;
+    let mut time = 0u64;
+    while written < size_bytes
+    {
+        time += 10;
+        let mut step = format!("#{}\n", time);
+        if time == 100
+        {
+            step.push_str("1!\n");
+        }
+        for i in 0..SIGNALS
+        {
+            step.push_str(&format!("b{:08b} s{}\n", (time as usize + i) % 256, i));
+        }
+        out.write_all(step.as_bytes())?;
+        written += step.len() as u64;
+    }
+    out.flush()
+}
+
+fn inputs() -> [PathBuf; 2]
+{
+    let size_mb = std::env::var("VCD_SYNC_BENCH_MB").ok().and_then(|mb| mb.parse().ok()).unwrap_or(100u64);
+    let dir = std::env::temp_dir();
+    let files = [dir.join(format!("vcd_sync_bench_{}mb_a.vcd", size_mb)),
+                 dir.join(format!("vcd_sync_bench_{}mb_b.vcd", size_mb))];
+    for file in &files
+    {
+        if !file.exists()
+        {
+            generate(file, size_mb << 20).expect("Can't generate benchmark input");
+        }
+    }
+    files
+}
+
+fn parse(c : &mut Criterion)
+{
+    let files = inputs();
+    let options = ParseOptions::default();
+    let mut group = c.benchmark_group("parse_two_files");
+    group.sample_size(10);
+
+    group.bench_function("serial", |b| b.iter(||
+    {
+        files.iter().map(|file| VCD::new(file, "top.reset", &options).unwrap()).collect::<Vec<_>>()
+    }));
+
+    group.bench_function("parallel", |b| b.iter(||
+    {
+        std::thread::scope(|scope|
+        {
+            let parsers : Vec<_> = files.iter().map(|file| scope.spawn(|| VCD::new(file, "top.reset", &options).unwrap())).collect();
+            parsers.into_iter().map(|parser| parser.join().unwrap()).collect::<Vec<_>>()
+        })
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Value change sections behind a valid header: collect_values must cope
+//! with any sequence of commands, declared or not, in order or not.
+
+#![no_main]
+
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use vcd::{IdCode, Parser};
+use vcd_sync::{ParseOptions, collect_values, signals};
+
+const HEADER : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset_n $end
+$var wire 4 \" state $end
+$var real 64 # level $end
+$upscope $end
+$enddefinitions $end
+";
+
+// Codes 1 to 3 are declared, the others aren't
+#[derive(Arbitrary, Debug)]
+enum Change
+{
+    Timestamp(u16),
+    Scalar(u8, u8),
+    Vector(u8, Vec<u8>),
+    Real(u8, f64),
+    Begin(u8),
+    End,
+}
+
+fn value(value : u8) -> char
+{
+    ['0', '1', 'x', 'z'][value as usize % 4]
+}
+
+fuzz_target!(|changes : Vec<Change>|
+{
+    let mut text = HEADER.to_string();
+    for change in &changes
+    {
+        let id = |id : &u8| IdCode::from(*id as u64 % 8);
+        let _ = match change
+        {
+            Change::Timestamp(timestamp) => writeln!(text, "#{}", timestamp),
+            Change::Scalar(code, bit) => writeln!(text, "{}{}", value(*bit), id(code)),
+            Change::Vector(code, bits) => writeln!(text, "b{} {}", bits.iter().map(|bit| value(*bit)).collect::<String>(), id(code)),
+            Change::Real(code, real) => writeln!(text, "r{} {}", real, id(code)),
+            Change::Begin(section) => writeln!(text, "{}", ["$dumpvars", "$dumpall", "$dumpoff", "$dumpon"][*section as usize % 4]),
+            Change::End => writeln!(text, "$end"),
+        };
+    }
+
+    let mut parser = Parser::new(Cursor::new(text.into_bytes()));
+    let header = parser.parse_header().unwrap();
+    let signals = signals(&header.items);
+    let reset = signals.first().map(|(_, code)| *code);
+    let _ = collect_values(Path::new("fuzz.vcd"), &signals, &mut parser, reset, &ParseOptions::default());
+});
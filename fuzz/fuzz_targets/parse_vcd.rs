@@ -0,0 +1,15 @@
+//! Whole files: VCD::new must return an error on bad input, never panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vcd_sync::{ParseOptions, VCD};
+
+fuzz_target!(|data : &[u8]|
+{
+    // VCD::new only reads files, one per fuzzing process is enough
+    let path = std::env::temp_dir().join(format!("vcd_sync_fuzz_{}.vcd", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let _ = VCD::new(&path, "top.reset_n", &ParseOptions::default());
+    let _ = VCD::with_offset(&path, 0, &ParseOptions::default());
+});
@@ -0,0 +1,93 @@
+//! Traces built in memory, e.g. by tests or simulation harnesses, instead of
+//! being parsed from a file.
+
+use std::path::PathBuf;
+
+use crate::{Result, ResetEdge, ResetPolarity, ResetTracker, Signal, Source, StoredValue, SyncPoint, TimescaleUnit,
+            TimestampValues, Value, VarType, VCD, VcdSyncError};
+
+/// Builds a [`VCD`] signal by signal and event by event.
+///
+/// `name` stands for the file path of a parsed trace, in error messages and
+/// in the provenance comment of the output.
+#[derive(Clone, Debug)]
+pub struct VcdBuilder
+{
+    pub name : PathBuf,
+    pub signals : Vec<Signal>,
+    pub timescale : (u32, TimescaleUnit),
+    values : TimestampValues,
+}
+
+impl VcdBuilder
+{
+    pub fn new(name : impl Into<PathBuf>, timescale_value : u32, timescale_unit : TimescaleUnit) -> VcdBuilder
+    {
+        VcdBuilder { name : name.into(), signals : Vec::new(), timescale : (timescale_value, timescale_unit), values : TimestampValues::new() }
+    }
+
+    /// Declares a signal from its full dotted path, e.g. `top.cpu.valid`
+    pub fn add_signal(&mut self, name : &str, width : u32, var_type : VarType) -> &mut VcdBuilder
+    {
+        let mut scope : Vec<String> = name.split('.').map(str::to_string).collect();
+        let name = scope.pop().unwrap_or_default();
+        self.signals.push(Signal { scope, name, width, var_type, source : 0, index : None, code : None });
+        self
+    }
+
+    /// Records a change of a declared signal, changes at the same timestamp
+    /// keep their order
+    pub fn add_event(&mut self, timestamp : u64, signal_name : &str, value : StoredValue) -> Result<()>
+    {
+        let id = self.signals.iter().position(|signal| signal.full_name() == signal_name)
+            .ok_or_else(|| VcdSyncError::UnknownSignal { signal : signal_name.to_string() })?;
+        self.values.entry(timestamp).or_default().push((id as u32, value));
+        Ok(())
+    }
+
+    /// Builds a trace synced on the last release of `reset_signal`, like
+    /// [`VCD::new`] would for the same file
+    pub fn build(self, reset_signal : &str, reset_polarity : ResetPolarity) -> Result<VCD>
+    {
+        let rst_index = self.signals.iter().position(|signal| signal.full_name() == reset_signal)
+            .ok_or_else(|| VcdSyncError::ResetSignalNotFound { signal : reset_signal.to_string(), file : self.name.clone() })?;
+
+        let mut reset = ResetTracker::new(reset_polarity);
+        for (timestamp, value) in self.values.iter()
+            .flat_map(|(timestamp, changes)| changes.iter().map(move |(id, value)| (*timestamp, *id, value)))
+            .filter(|(_, id, _)| *id as usize == rst_index)
+            .map(|(timestamp, _, value)| (timestamp, value))
+        {
+            // A vector or real reset is never released
+            let value = match value
+            {
+                StoredValue::Scalar(value) => *value,
+                _ => Value::X,
+            };
+            reset.update(timestamp, value);
+        }
+        let rst_end = match reset.edge
+        {
+            ResetEdge::Deasserted(rst_end) => rst_end,
+            ResetEdge::NeverDeasserted =>
+                return Err(VcdSyncError::ResetNeverDeasserted { signal : reset_signal.to_string(), file : self.name }),
+            ResetEdge::NotSeen =>
+                return Err(VcdSyncError::ResetNotSeen { signal : reset_signal.to_string(), file : self.name }),
+        };
+
+        let sync = SyncPoint::ResetSignal(rst_end);
+        let (timescale_value, timescale_unit) = self.timescale;
+        Ok(VCD {
+            timescale_value,
+            timescale_unit,
+            sources : vec![Source { path : self.name, sync, timeskew : 0, prefix : None, dump_sections : Vec::new() }],
+            signals : self.signals,
+            values : self.values,
+            sync,
+            rst_id : None,
+            rst_polarity : reset_polarity,
+            version : None,
+            date : None,
+        })
+    }
+}
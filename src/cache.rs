@@ -0,0 +1,182 @@
+//! On-disk cache of parsed traces, as used by `--cache-dir`.
+//!
+//! A cached trace is only reused when the input file and the settings it was
+//! parsed with are unchanged. The cache file name is a FNV-1a hash of the
+//! bincode encoding of:
+//!
+//! - [`CACHE_FORMAT`] and the vcd_sync version, as the cache format follows
+//!   the `VCD` fields,
+//! - the canonical path of the input,
+//! - its size and modification time,
+//! - the parse settings (reset signal, filters...) given by the caller.
+//!
+//! Both the encoding and the hash are fixed, so the same inputs give the
+//! same name across runs, builds and toolchains.
+//!
+//! Any change gives a new name, so stale entries are never read again but
+//! they are never deleted either: clear the directory to reclaim space.
+//! Unreadable or corrupt entries are parsed from scratch and overwritten.
+
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Result, TimescaleUnit, VCD};
+
+/// Extension of the cache files
+pub const CACHE_EXTENSION : &str = "vcdcache";
+
+/// Version of the cache key and entry layout, bumped when either changes
+/// in a way the vcd_sync version doesn't cover
+pub const CACHE_FORMAT : u32 = 1;
+
+// Everything a cache entry depends on, in a fixed order
+#[derive(Serialize)]
+struct CacheKey<'a, S : Serialize>
+{
+    format : u32,
+    version : &'a str,
+    path : PathBuf,
+    len : u64,
+    // Seconds and nanoseconds since the epoch
+    modified : (u64, u32),
+    settings : &'a S,
+}
+
+const FNV_OFFSET : u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME : u128 = 0x0000000001000000000000000000013b;
+
+fn fnv1a(bytes : &[u8]) -> u128
+{
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| (hash ^ u128::from(byte)).wrapping_mul(FNV_PRIME))
+}
+
+/// Path of the cache entry of `file_path` parsed with `settings`
+pub fn cache_path<S : Serialize>(cache_dir : &Path, file_path : &Path, settings : &S) -> Result<PathBuf>
+{
+    let metadata = fs::metadata(file_path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let key = CacheKey
+    {
+        format : CACHE_FORMAT,
+        version : env!("CARGO_PKG_VERSION"),
+        path : fs::canonicalize(file_path)?,
+        len : metadata.len(),
+        modified : (modified.as_secs(), modified.subsec_nanos()),
+        settings,
+    };
+    let bytes = bincode::serialize(&key).map_err(io::Error::other)?;
+    Ok(cache_dir.join(format!("{:032x}.{}", fnv1a(&bytes), CACHE_EXTENSION)))
+}
+
+/// Loads the cached trace of `file_path` or parses it with `parse` and caches it.
+///
+/// `settings` must describe everything `parse` depends on besides the file
+/// itself. Failing to read or write the cache only prints a warning.
+pub fn cached_parse<S, F>(cache_dir : &Path, file_path : &Path, settings : &S, parse : F) -> Result<VCD>
+where
+    S : Serialize,
+    F : FnOnce() -> Result<VCD>,
+{
+    let cache_file = cache_path(cache_dir, file_path, settings)?;
+    if cache_file.exists()
+    {
+        match File::open(&cache_file).map_err(bincode::Error::from).and_then(|file| bincode::deserialize_from(BufReader::new(file)))
+        {
+            Ok(vcd) =>
+            {
+                eprintln!("Loaded cached trace : {}", cache_file.display());
+                return Ok(vcd);
+            },
+            Err(error) => warning!("Ignoring unreadable cache {}: {}", cache_file.display(), error),
+        }
+    }
+
+    let vcd = parse()?;
+    let written = fs::create_dir_all(cache_dir).map_err(bincode::Error::from)
+        .and_then(|_| File::create(&cache_file).map_err(bincode::Error::from))
+        .and_then(|file| bincode::serialize_into(BufWriter::new(file), &vcd));
+    if let Err(error) = written
+    {
+        warning!("Can't write cache {}: {}", cache_file.display(), error);
+    }
+    Ok(vcd)
+}
+
+// The vcd types have no serde support but round-trip through their text form
+pub(crate) mod as_str
+{
+    use super::*;
+
+    pub fn serialize<T : Display, S : Serializer>(value : &T, serializer : S) -> std::result::Result<S::Ok, S::Error>
+    {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer : D) -> std::result::Result<T, D::Error>
+    where
+        T : FromStr,
+        T::Err : Display,
+        D : Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+pub(crate) mod vec_as_str
+{
+    use super::*;
+
+    pub fn serialize<T : Display, S : Serializer>(values : &[T], serializer : S) -> std::result::Result<S::Ok, S::Error>
+    {
+        serializer.collect_seq(values.iter().map(ToString::to_string))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer : D) -> std::result::Result<Vec<T>, D::Error>
+    where
+        T : FromStr,
+        T::Err : Display,
+        D : Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|value| value.parse().map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+pub(crate) mod option_as_str
+{
+    use super::*;
+
+    pub fn serialize<T : Display, S : Serializer>(value : &Option<T>, serializer : S) -> std::result::Result<S::Ok, S::Error>
+    {
+        match value
+        {
+            Some(value) => serializer.serialize_some(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer : D) -> std::result::Result<Option<T>, D::Error>
+    where
+        T : FromStr,
+        T::Err : Display,
+        D : Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| value.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+// Timescales as their value and unit text, e.g. `(10, "ns")`
+pub(crate) fn serialize_timescale<S : Serializer>(timescale : &Option<(u32, TimescaleUnit)>, serializer : S) -> std::result::Result<S::Ok, S::Error>
+{
+    timescale.map(|(value, unit)| (value, unit.to_string())).serialize(serializer)
+}
@@ -0,0 +1,77 @@
+//! Severity labels of the messages printed by vcd_sync, `Warning` in yellow
+//! and `Error` in red when written to a terminal.
+//!
+//! Colors are picked per stream, so a redirected stderr or a `--stats` file
+//! never gets escape codes. `NO_COLOR` or [`set_color`] turn them off.
+
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR : AtomicBool = AtomicBool::new(true);
+
+/// How bad a message is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level
+{
+    Warning,
+    Error,
+}
+
+/// Where a message is printed, only terminals get colors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream
+{
+    Stdout,
+    Stderr,
+}
+
+/// Allows colors on terminals (the default) or never prints any
+pub fn set_color(enabled : bool)
+{
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+fn colored(stream : Stream) -> bool
+{
+    let terminal = match stream
+    {
+        Stream::Stdout => io::stdout().is_terminal(),
+        Stream::Stderr => io::stderr().is_terminal(),
+    };
+    terminal && COLOR.load(Ordering::Relaxed) && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// `Warning` or `Error`, colored for `stream` when it's a terminal
+pub fn label(level : Level, stream : Stream) -> String
+{
+    let (name, color) = match level
+    {
+        Level::Warning => ("Warning", "33"),
+        Level::Error => ("Error", "31"),
+    };
+    match colored(stream)
+    {
+        true => format!("\x1b[{}m{}\x1b[0m", color, name),
+        false => name.to_string(),
+    }
+}
+
+/// Prints `Warning: message` to stderr, see [`warning!`](crate::warning)
+pub fn warn(message : fmt::Arguments)
+{
+    eprintln!("{}: {}", label(Level::Warning, Stream::Stderr), message);
+}
+
+/// Prints `Error: message` to stderr
+pub fn error(message : fmt::Arguments)
+{
+    eprintln!("{}: {}", label(Level::Error, Stream::Stderr), message);
+}
+
+/// `eprintln!` of a warning, with its label colored on terminals
+#[macro_export]
+macro_rules! warning
+{
+    ($($arg : tt)*) => { $crate::diagnostic::warn(format_args!($($arg)*)) };
+}
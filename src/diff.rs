@@ -0,0 +1,193 @@
+//! Value differences between two traces aligned on their reset, as reported
+//! by `vcd_sync diff`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Result, StoredValue, TimestampValues, VCD, VcdSyncError, WriteOptions, write_vcd_to_file};
+use crate::fst::fst_bits;
+
+/// A timestamp at which a signal has different values in the two sources
+#[derive(Clone, Debug, Serialize)]
+pub struct Difference
+{
+    pub timestamp : u64,
+    /// Full dotted path of the signal
+    pub signal : String,
+    /// Value in the first source, `None` before its first change
+    pub left : Option<String>,
+    /// Value in the second source, `None` before its first change
+    pub right : Option<String>,
+}
+
+// Short vectors are left-extended the VCD way so that b1 and b001 compare equal
+fn same_value(left : &StoredValue, right : &StoredValue, width : u32) -> bool
+{
+    match (left, right)
+    {
+        (StoredValue::Real(left), StoredValue::Real(right)) => left == right,
+        (StoredValue::Str(left), StoredValue::Str(right)) => left == right,
+        _ => fst_bits(left, width).is_some_and(|bits| Some(bits) == fst_bits(right, width)),
+    }
+}
+
+/// Compares the signals declared with the same full path in `reference` and
+/// `actual`, restricted to `signals` if it's not empty, once both are
+/// aligned on their sync points.
+///
+/// Values are compared after every change of either signal, each timestamp
+/// where they differ is reported. Both traces start with every signal at 0,
+/// like a merge, then with their own changes at timestamp 0.
+///
+/// A name of `signals` that isn't declared in both traces is an
+/// [`VcdSyncError::UnknownSignal`], every other missing one is warned about.
+pub fn diff(reference : &VCD, actual : &VCD, signals : &[String]) -> Result<Vec<Difference>>
+{
+    let merged = aligned(reference, actual)?;
+    let (pairs, pair_of) = common_pairs(&merged, signals);
+    let mut missing = signals.iter().filter(|signal| !pairs.iter().any(|(name, ..)| name == *signal));
+    if let Some(signal) = missing.next()
+    {
+        for other in missing
+        {
+            warning!("Signal {} is not declared in both traces", other);
+        }
+        return Err(VcdSyncError::UnknownSignal { signal : signal.clone() });
+    }
+    let mut current : Vec<Option<&StoredValue>> = vec![None; merged.signals.len()];
+    let mut differences = Vec::new();
+    for (timestamp, changes) in &merged.values
+    {
+        for pair in changed_pairs(&pair_of, changes, &mut current)
+        {
+            let (name, left_id, right_id, width) = &pairs[pair];
+            let (left, right) = (current[*left_id], current[*right_id]);
+            if !same_state(left, right, *width)
+            {
+                differences.push(Difference {
+                    timestamp : *timestamp,
+                    signal : name.clone(),
+                    left : left.map(|value| value.to_string()),
+                    right : right.map(|value| value.to_string()),
+                });
+            }
+        }
+    }
+    Ok(differences)
+}
+
+// Both traces in one, every signal at 0 at timestamp 0 before their own
+// changes there, which a merge replaces
+fn aligned(reference : &VCD, actual : &VCD) -> Result<VCD>
+{
+    let mut merged = reference.clone();
+    merged.merge_keeping_start(actual.clone())?;
+    Ok(merged)
+}
+
+/// Writes to `output_file` the changes of `actual` differing from
+/// `reference`, both aligned on their reset like `vcd_sync diff` does.
+///
+/// A signal of both traces is written at each timestamp where its value in
+/// `actual` differs from the one in `reference`, starting values included
+/// like [`diff`]. Signals only in `actual` are written whole, the ones only
+/// in `reference` too with `include_reference_only`.
+pub fn write_vcd_diff(reference : &VCD, actual : &VCD, output_file : &Path, include_reference_only : bool) -> Result<()>
+{
+    let mut merged = aligned(reference, actual)?;
+    let (pairs, pair_of) = common_pairs(&merged, &[]);
+
+    let mut values = TimestampValues::new();
+    let mut current : Vec<Option<&StoredValue>> = vec![None; merged.signals.len()];
+    for (timestamp, changes) in &merged.values
+    {
+        let mut written : Vec<_> = changes.iter()
+            .filter(|(id, _)| !pair_of.contains_key(&(*id as usize)))
+            .cloned()
+            .collect();
+        for pair in changed_pairs(&pair_of, changes, &mut current)
+        {
+            let (_, left_id, right_id, width) = pairs[pair];
+            if let Some(right) = current[right_id].filter(|_| !same_state(current[left_id], current[right_id], width))
+            {
+                written.push((right_id as u32, right.clone()));
+            }
+        }
+        if !written.is_empty()
+        {
+            values.insert(*timestamp, written);
+        }
+    }
+
+    // Common signals are written once, as the ones of actual
+    let mut dropped : HashSet<u32> = pairs.iter().map(|(_, left_id, _, _)| *left_id as u32).collect();
+    if !include_reference_only
+    {
+        dropped.extend(merged.signals.iter().enumerate().filter(|(_, signal)| signal.source == 0).map(|(id, _)| id as u32));
+    }
+    merged.values = values;
+    merged.remove_signals(&dropped);
+    write_vcd_to_file(merged, output_file, &WriteOptions::default())
+}
+
+type Pair = (String, usize, usize, u32);
+
+// Signals declared with the same full path in both sources, as (name, left
+// id, right id, width), and the index in them of every paired signal id
+fn common_pairs(merged : &VCD, signals : &[String]) -> (Vec<Pair>, HashMap<usize, usize>)
+{
+    let left_ids : HashMap<String, usize> = merged.signals.iter().enumerate()
+        .filter(|(_, signal)| signal.source == 0)
+        .map(|(id, signal)| (signal.full_name(), id))
+        .collect();
+    // Signal id -> index in pairs
+    let mut pairs = Vec::new();
+    let mut pair_of = HashMap::new();
+    for (id, signal) in merged.signals.iter().enumerate().filter(|(_, signal)| signal.source == 1)
+    {
+        let name = signal.full_name();
+        if let Some(&left_id) = left_ids.get(&name)
+        {
+            if signals.is_empty() || signals.contains(&name)
+            {
+                let width = signal.width.max(merged.signals[left_id].width);
+                pair_of.insert(left_id, pairs.len());
+                pair_of.insert(id, pairs.len());
+                pairs.push((name, left_id, id, width));
+            }
+        }
+    }
+    (pairs, pair_of)
+}
+
+// Index of the pairs with a change in `changes`, once each, after `current`
+// is updated
+fn changed_pairs<'a>(pair_of : &HashMap<usize, usize>,
+                     changes : &'a [(u32, StoredValue)],
+                     current : &mut [Option<&'a StoredValue>]) -> Vec<usize>
+{
+    let mut changed = Vec::new();
+    for (id, value) in changes
+    {
+        current[*id as usize] = Some(value);
+        if let Some(&pair) = pair_of.get(&(*id as usize))
+        {
+            changed.push(pair);
+        }
+    }
+    changed.sort_unstable();
+    changed.dedup();
+    changed
+}
+
+// Two signals not changed yet are the same
+fn same_state(left : Option<&StoredValue>, right : Option<&StoredValue>, width : u32) -> bool
+{
+    match (left, right)
+    {
+        (Some(left), Some(right)) => same_value(left, right, width),
+        _ => left.is_none() && right.is_none(),
+    }
+}
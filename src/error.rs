@@ -0,0 +1,83 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::{SyncPoint, TimescaleUnit};
+
+/// Everything that can go wrong while parsing, merging or writing traces
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum VcdSyncError
+{
+    #[error("Timescale not found in {}", file.display())]
+    TimescaleNotFound { file : PathBuf },
+
+    #[error("Timescale {value} {unit} {reason}")]
+    InvalidTimescale { value : u32, unit : TimescaleUnit, reason : &'static str },
+
+    #[error("Timestamp {timestamp} overflows when converted from {} {} to {} {}", from.0, from.1, to.0, to.1)]
+    TimestampOverflow { timestamp : u64, from : (u32, TimescaleUnit), to : (u32, TimescaleUnit) },
+
+    #[error("Timestamp {timestamp} overflows when shifted by a timeskew of {timeskew}")]
+    ShiftOverflow { timestamp : u64, timeskew : u64 },
+
+    #[error("Signal {signal} is not declared")]
+    UnknownSignal { signal : String },
+
+    #[error("Reset signal {signal} not found in {}", file.display())]
+    ResetSignalNotFound { signal : String, file : PathBuf },
+
+    #[error("None of the reset signals {tried} is found in {}", file.display())]
+    NoResetSignalFound { tried : String, file : PathBuf },
+
+    #[error("{} was parsed but has no value change, check that the simulation dumped its signals", file.display())]
+    EmptyFile { file : PathBuf },
+
+    #[error("The signal filters leave no changing signal of {}", file.display())]
+    NoSignalSelected { file : PathBuf },
+
+    #[error("Reset signal {signal} is never released in {}", file.display())]
+    ResetNeverDeasserted { signal : String, file : PathBuf },
+
+    #[error("Reset signal {signal} never changes in {}", file.display())]
+    ResetNotSeen { signal : String, file : PathBuf },
+
+    #[error("Non-monotonic timestamp in {}: #{next} after #{prev}", file.display())]
+    NonMonotonicTimestamp { file : PathBuf, prev : u64, next : u64 },
+
+    #[error("Output {} doesn't match the merged trace: {reason}", file.display())]
+    VerifyFailed { file : PathBuf, reason : String },
+
+    #[error("Can't merge traces placed differently in time: {first} and {second}")]
+    SyncMismatch { first : SyncPoint, second : SyncPoint },
+
+    #[error("No VCD file given")]
+    NoInput,
+
+    #[error(transparent)]
+    IoError(io::Error),
+
+    #[error(transparent)]
+    ParseError(#[from] vcd::ParseError),
+
+    #[error(transparent)]
+    FstError(#[from] fst_writer::FstWriteError),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+}
+
+// The vcd parser reports its errors wrapped in io::Error
+impl From<io::Error> for VcdSyncError
+{
+    fn from(error : io::Error) -> VcdSyncError
+    {
+        match error.get_ref().and_then(|inner| inner.downcast_ref::<vcd::ParseError>())
+        {
+            Some(parse_error) => VcdSyncError::ParseError(parse_error.clone()),
+            None => VcdSyncError::IoError(error),
+        }
+    }
+}
+
+/// Result type of the library
+pub type Result<T> = std::result::Result<T, VcdSyncError>;
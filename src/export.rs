@@ -0,0 +1,85 @@
+//! Tabular exports of a (merged) trace, one record per value change.
+
+use std::io::Write;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Result, StoredValue, VCD, WriteOptions, create_output, prepare_output, trim_values};
+
+// Quotes a CSV field when it needs to be
+fn csv_field(field : &str) -> String
+{
+    match field.contains([',', '"', '\n'])
+    {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Writes a (merged) trace as CSV with a `timestamp,signal_name,value` header,
+/// to stdout if `output_file` is `-`
+pub fn write_csv(mut merged : VCD, output_file : &Path, options : &WriteOptions) -> Result<()>
+{
+    prepare_output(&mut merged, options);
+    let names : Vec<String> = merged.signals.iter().map(|signal| csv_field(&signal.full_name())).collect();
+
+    let mut output = create_output(output_file)?;
+    writeln!(output, "timestamp,signal_name,value")?;
+    for (timestamp, values) in trim_values(merged.values, options)
+    {
+        for (id, value) in values
+        {
+            writeln!(output, "{},{},{}", timestamp, names[id as usize], csv_field(&value.to_string()))?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonChange<'a>
+{
+    ts : u64,
+    sig : &'a str,
+    val : JsonValue,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonValue
+{
+    Bits(String),
+    Real(f64),
+}
+
+/// Writes a (merged) trace as a JSON array of `{"ts", "sig", "val"}` objects,
+/// to stdout if `output_file` is `-`. Values are bit strings, numbers for
+/// real signals or the text of string signals.
+pub fn write_json(mut merged : VCD, output_file : &Path, options : &WriteOptions) -> Result<()>
+{
+    prepare_output(&mut merged, options);
+    let names : Vec<String> = merged.signals.iter().map(|signal| signal.full_name()).collect();
+
+    // Streamed, a merged trace can be much larger than memory as JSON
+    let mut output = create_output(output_file)?;
+    let mut separator = "";
+    write!(output, "[")?;
+    for (timestamp, values) in trim_values(merged.values, options)
+    {
+        for (id, value) in values
+        {
+            let val = match value
+            {
+                StoredValue::Real(value) => JsonValue::Real(value),
+                value => JsonValue::Bits(value.to_string()),
+            };
+            write!(output, "{}\n  ", separator)?;
+            serde_json::to_writer(&mut output, &JsonChange { ts : timestamp, sig : &names[id as usize], val })?;
+            separator = ",";
+        }
+    }
+    writeln!(output, "\n]")?;
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use fst_writer::{FstFileType, FstHeaderWriter, FstInfo, FstScopeType, FstSignalId, FstSignalType,
+                 FstVarDirection, FstVarType};
+
+use crate::{Result, VcdSyncError, ReferenceIndex, ScopeNode, Signal, StoredValue, TimescaleUnit, VarType, VCD, WriteOptions, prepare_output, trim_values};
+
+// FST stores the timescale as a power of ten of a second
+fn timescale_exponent(value : u32, unit : TimescaleUnit) -> Result<i8>
+{
+    let unit_exponent = -(unit.divisor().ilog10() as i8);
+    match value
+    {
+        1 => Ok(unit_exponent),
+        10 => Ok(unit_exponent + 1),
+        100 => Ok(unit_exponent + 2),
+        _ => Err(VcdSyncError::InvalidTimescale { value, unit, reason : "can't be represented in FST" }),
+    }
+}
+
+// FST wants exactly one character per bit, short VCD vectors are
+// left-extended with 0, or with x / z when that is their leftmost bit.
+// Reals and strings have no bits
+pub(crate) fn fst_bits(value : &StoredValue, width : u32) -> Option<Vec<u8>>
+{
+    let bits : Vec<u8> = match value
+    {
+        StoredValue::Scalar(value) => value.to_string().into_bytes(),
+        StoredValue::Vector(vector) => vector.to_string().into_bytes(),
+        StoredValue::Real(_) | StoredValue::Str(_) => return None,
+    };
+    let width = width as usize;
+    if bits.len() >= width
+    {
+        return Some(bits[bits.len() - width..].to_vec());
+    }
+
+    let pad = match bits.first()
+    {
+        Some(b'x') => b'x',
+        Some(b'z') => b'z',
+        _ => b'0',
+    };
+    let mut padded = vec![pad; width - bits.len()];
+    padded.extend(bits);
+    Some(padded)
+}
+
+fn fst_var_type(var_type : VarType) -> FstVarType
+{
+    match var_type
+    {
+        VarType::Event => FstVarType::Event,
+        VarType::Integer => FstVarType::Integer,
+        VarType::Parameter => FstVarType::Parameter,
+        VarType::Real => FstVarType::Real,
+        VarType::Reg => FstVarType::Reg,
+        VarType::Supply0 => FstVarType::Supply0,
+        VarType::Supply1 => FstVarType::Supply1,
+        VarType::Time => FstVarType::Time,
+        VarType::Tri => FstVarType::Tri,
+        VarType::TriAnd => FstVarType::TriAnd,
+        VarType::TriOr => FstVarType::TriOr,
+        VarType::TriReg => FstVarType::TriReg,
+        VarType::Tri0 => FstVarType::Tri0,
+        VarType::Tri1 => FstVarType::Tri1,
+        VarType::WAnd => FstVarType::Wand,
+        VarType::WOr => FstVarType::Wor,
+        VarType::String => FstVarType::GenericString,
+        _ => FstVarType::Wire,
+    }
+}
+
+fn write_scope<W>(node : &ScopeNode,
+                  writer : &mut FstHeaderWriter<W>,
+                  signals : &[Signal],
+                  signals_map : &mut Vec<Option<FstSignalId>>) -> Result<()>
+where
+    W: std::io::Write + std::io::Seek,
+{
+    for id in &node.wires
+    {
+        let signal = &signals[*id as usize];
+        // fst-writer can't encode real or string value changes yet
+        if matches!(signal.var_type, VarType::Real | VarType::String)
+        {
+            warning!("{:?} signal {} is not supported in FST output, skipping it", signal.var_type, signal.full_name());
+            continue;
+        }
+        let name = match signal.index
+        {
+            Some(ReferenceIndex::BitSelect(index)) => format!("{}_{}", signal.name, index),
+            _ => signal.name.clone(),
+        };
+        let signal_id = writer.var(&name, FstSignalType::bit_vec(signal.width), fst_var_type(signal.var_type),
+                                   FstVarDirection::Implicit, None)?;
+        signals_map[*id as usize] = Some(signal_id);
+    }
+    for (name, scope) in &node.scopes
+    {
+        writer.scope(name, "", FstScopeType::Module)?;
+        write_scope(scope, writer, signals, signals_map)?;
+        writer.up_scope()?;
+    }
+    Ok(())
+}
+
+/// Writes a (merged) trace to `output_file` in the FST format.
+///
+/// FST is only an output format: the result can't be fed back to
+/// [`VCD::new`] to be merged with another trace later on.
+pub fn write_fst(mut merged : VCD, output_file : &Path, options : &WriteOptions) -> Result<()>
+{
+    prepare_output(&mut merged, options);
+    let values = trim_values(merged.values, options);
+    // Same header metadata as a VCD output, FST always has both fields
+    let (version, date) = match options.strip_header_metadata
+    {
+        true => (None, None),
+        false => (merged.version, merged.date),
+    };
+    let info = FstInfo {
+        start_time : values.keys().next().copied().unwrap_or(0),
+        timescale_exponent : timescale_exponent(merged.timescale_value, merged.timescale_unit)?,
+        version : version.unwrap_or_else(|| format!("vcd_sync {}", env!("CARGO_PKG_VERSION"))),
+        date : date.unwrap_or_default(),
+        file_type : FstFileType::Verilog,
+    };
+    let mut header = fst_writer::open_fst(output_file, &info)?;
+
+    // fst-writer has no array indexes, elements are told apart by their name
+    if merged.signals.iter().any(|signal| matches!(signal.index, Some(ReferenceIndex::BitSelect(_))))
+    {
+        warning!("Array elements are written to FST as NAME_INDEX signals");
+    }
+    let root = ScopeNode::build(&merged.signals, options);
+    let mut signals_map = vec![None; merged.signals.len()];
+    write_scope(&root, &mut header, &merged.signals, &mut signals_map)?;
+
+    let mut body = header.finish()?;
+    for (timestamp, values) in values
+    {
+        body.time_change(timestamp)?;
+        for (id, value) in values
+        {
+            // Real and string signals are skipped in the header
+            let width = merged.signals[id as usize].width;
+            if let (Some(signal_id), Some(bits)) = (signals_map[id as usize], fst_bits(&value, width))
+            {
+                body.signal_change(signal_id, &bits)?;
+            }
+        }
+    }
+    body.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+//! Unbalanced scopes in the header, which some exporters emit but the vcd
+//! crate rejects.
+//!
+//! Traces are read through [`ScopeBalancer`], which drops a `$upscope $end`
+//! closing more scopes than are open before it reaches the parser, with a
+//! warning. The signals declared after it keep the scopes left open. Only
+//! the keyword starting a header command is looked at, the words of the
+//! others (scope names, comment text) are passed through as-is.
+
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+/// Line by line rewrite of the header commands preceding `$enddefinitions`
+pub(crate) struct ScopeBalancer<R>
+{
+    inner : R,
+    line : Vec<u8>,
+    pos : usize,
+    in_body : bool,
+    // Inside a command, until its $end, and whether it's $enddefinitions
+    in_command : bool,
+    in_enddefinitions : bool,
+    // The $end of a dropped $upscope, which can be on the next line
+    unmatched : bool,
+    // Scopes open at the current header line, and its number
+    depth : usize,
+    line_number : usize,
+    file_path : PathBuf,
+}
+
+impl<R : BufRead> ScopeBalancer<R>
+{
+    /// Reads the trace of `file_path`, only named in warnings
+    pub(crate) fn new(inner : R, file_path : &Path) -> ScopeBalancer<R>
+    {
+        ScopeBalancer { inner, line : Vec::new(), pos : 0, in_body : false, in_command : false, in_enddefinitions : false,
+                        unmatched : false, depth : 0, line_number : 0, file_path : file_path.to_path_buf() }
+    }
+
+    fn rewrite_line(&mut self)
+    {
+        if self.in_body
+        {
+            return;
+        }
+
+        self.line_number += 1;
+        let mut removed = Vec::new();
+        let mut start = 0;
+        while let Some(len) = self.line[start..].iter().position(|byte| !byte.is_ascii_whitespace())
+        {
+            start += len;
+            let end = self.line[start..].iter().position(u8::is_ascii_whitespace).map_or(self.line.len(), |len| start + len);
+            let token = &self.line[start..end];
+            if std::mem::take(&mut self.unmatched) && token == b"$end"
+            {
+                removed.push(start..end);
+                start = end;
+                continue;
+            }
+            match token
+            {
+                b"$end" if self.in_enddefinitions =>
+                {
+                    self.in_body = true;
+                    break;
+                },
+                b"$end" => self.in_command = false,
+                _ if self.in_command => (),
+                b"$upscope" if self.depth == 0 =>
+                {
+                    warning!("$upscope without a matching $scope at line {} of {}, ignored", self.line_number, self.file_path.display());
+                    removed.push(start..end);
+                    self.unmatched = true;
+                },
+                b"$upscope" =>
+                {
+                    self.depth -= 1;
+                    self.in_command = true;
+                },
+                b"$scope" =>
+                {
+                    self.depth += 1;
+                    self.in_command = true;
+                },
+                b"$enddefinitions" => self.in_enddefinitions = true,
+                _ => self.in_command = token.starts_with(b"$"),
+            }
+            start = end;
+        }
+        for range in removed.into_iter().rev()
+        {
+            self.line.drain(range);
+        }
+    }
+}
+
+impl<R : BufRead> Read for ScopeBalancer<R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize>
+    {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R : BufRead> BufRead for ScopeBalancer<R>
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>
+    {
+        if self.pos >= self.line.len()
+        {
+            self.line.clear();
+            self.pos = 0;
+            self.inner.read_until(b'\n', &mut self.line)?;
+            self.rewrite_line();
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt : usize)
+    {
+        self.pos += amt;
+    }
+}
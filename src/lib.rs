@@ -0,0 +1,2925 @@
+//! Merge and resynchronize VCD files based on a common reset signal.
+//!
+//! Each trace is parsed into a [`VCD`], brought to a common timescale with
+//! [`TimescaleNormalizer`], folded into a reference trace with [`VCD::merge`]
+//! and finally written back with [`write_vcd_to_file`].
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use flate2::bufread::GzDecoder;
+use glob::Pattern;
+use vcd::Command::{Begin, ChangeScalar, ChangeVector, ChangeReal, ChangeString, End, Timestamp};
+use vcd::{Parser, ScopeItem, SimulationCommand};
+
+pub use vcd::{Value, Vector, IdCode, ReferenceIndex, TimescaleUnit, VarType};
+
+#[macro_use]
+pub mod diagnostic;
+mod error;
+pub use error::{Result, VcdSyncError};
+mod fst;
+pub use fst::write_fst;
+mod export;
+pub use export::{write_csv, write_json};
+mod builder;
+pub use builder::VcdBuilder;
+pub mod diff;
+pub mod stats;
+#[cfg(feature = "serde")]
+pub mod cache;
+pub mod validate;
+#[cfg(feature = "progress")]
+mod progress;
+mod radix;
+use radix::RadixReader;
+mod header;
+use header::ScopeBalancer;
+
+// Signal / Id code
+pub type SignalsCode = Vec<(Signal, IdCode)>;
+// Time stamp  : [Value Changed]
+pub type TimestampValues = BTreeMap<u64, Vec<(u32, StoredValue)>>;
+// One entry of TimestampValues, as streamed out
+type TimestampChanges = (u64, Vec<(u32, StoredValue)>);
+
+/// A pulse of a signal shorter than a threshold, see [`VCD::glitches`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Glitch
+{
+    /// Index of the signal in [`VCD::signals`]
+    pub signal : u32,
+    /// Start of the pulse
+    pub timestamp : u64,
+    /// Ticks until the signal goes back to its previous value
+    pub width : u64,
+}
+
+/// A variable of a trace
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signal
+{
+    /// Path of the scopes the variable is declared in
+    pub scope : Vec<String>,
+    pub name : String,
+    /// Width in bits
+    pub width : u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::as_str"))]
+    pub var_type : VarType,
+    /// Index in [`VCD::sources`] of the file the signal comes from
+    pub source : usize,
+    /// Bit range of a bus (`[7:0]`) or element of an array (`[3]`) as
+    /// declared after the name, elements of an array share the same name
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::option_as_str"))]
+    pub index : Option<ReferenceIndex>,
+    /// IdCode of the variable in its input file, `None` when built
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::option_as_str"))]
+    pub code : Option<IdCode>,
+}
+
+impl Signal
+{
+    /// Dotted path of the signal, e.g. `top.cpu.valid`
+    pub fn full_name(&self) -> String
+    {
+        full_name(&self.scope, &self.name)
+    }
+}
+
+/// A value change as stored in memory, a single bit, a bus, a real number or
+/// the text of a string variable
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StoredValue
+{
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::as_str"))]
+    Scalar(Value),
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::as_str"))]
+    Vector(Vector),
+    Real(f64),
+    Str(String),
+}
+
+// Bits for scalars and vectors, a number for reals, the text of strings
+impl fmt::Display for StoredValue
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            StoredValue::Scalar(value) => write!(f, "{}", value),
+            StoredValue::Vector(value) => write!(f, "{}", value),
+            StoredValue::Real(value) => write!(f, "{}", value),
+            StoredValue::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Level at which the reset signal holds the design in reset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResetPolarity
+{
+    #[default]
+    ActiveLow,
+    ActiveHigh,
+}
+
+impl ResetPolarity
+{
+    // Value the reset signal takes when it is released
+    fn deasserted(self) -> Value
+    {
+        match self
+        {
+            ResetPolarity::ActiveLow => Value::V1,
+            ResetPolarity::ActiveHigh => Value::V0,
+        }
+    }
+}
+
+/// What [`collect_values`] saw of the reset signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetEdge
+{
+    /// Last release of the reset, the trace sync point
+    Deasserted(u64),
+    /// The reset signal changed but was never released after being asserted
+    NeverDeasserted,
+    /// No value change of the reset signal in the whole trace
+    NotSeen,
+}
+
+// Follows the changes of the reset signal, in timestamp order, to its last
+// release. For an active low reset we wait for the last 0 -> 1 edge (1 -> 0
+// for an active high one) because it means the reset is not active anymore,
+// that timestamp is used to sync the traces. A reset that starts released and
+// never gets asserted has no such edge.
+pub(crate) struct ResetTracker
+{
+    polarity : ResetPolarity,
+    value : Option<Value>,
+    pub(crate) edge : ResetEdge,
+}
+
+impl ResetTracker
+{
+    pub(crate) fn new(polarity : ResetPolarity) -> ResetTracker
+    {
+        ResetTracker { polarity, value : None, edge : ResetEdge::NotSeen }
+    }
+
+    pub(crate) fn update(&mut self, timestamp : u64, value : Value)
+    {
+        let released = value == self.polarity.deasserted();
+        match self.value
+        {
+            Some(previous) if released && previous != value => self.edge = ResetEdge::Deasserted(timestamp),
+            _ if self.edge == ResetEdge::NotSeen => self.edge = ResetEdge::NeverDeasserted,
+            _ => (),
+        }
+        self.value = Some(value);
+    }
+}
+
+/// Selects signals by glob patterns matched against their full dotted path,
+/// or by the scope subtree they are declared in
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SignalFilter
+{
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::cache::vec_as_str::serialize"))]
+    pub include : Vec<Pattern>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::cache::vec_as_str::serialize"))]
+    pub exclude : Vec<Pattern>,
+    /// Dotted scope paths, e.g. `top.pcie` for every signal below it
+    pub include_scopes : Vec<String>,
+    pub exclude_scopes : Vec<String>,
+}
+
+impl SignalFilter
+{
+    /// A signal is kept if it matches an include pattern or is below an
+    /// include scope (or there are none of either), and matches no exclude
+    /// pattern nor scope
+    pub fn is_selected(&self, full_name : &str) -> bool
+    {
+        let in_scope = |scope : &String| full_name.strip_prefix(scope.as_str()).is_some_and(|rest| rest.starts_with('.'));
+        let included = (self.include.is_empty() && self.include_scopes.is_empty())
+            || self.include.iter().any(|p| p.matches(full_name))
+            || self.include_scopes.iter().any(in_scope);
+        included
+            && !self.exclude.iter().any(|p| p.matches(full_name))
+            && !self.exclude_scopes.iter().any(in_scope)
+    }
+}
+
+/// Options controlling how a trace is parsed by [`VCD::new`]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ParseOptions
+{
+    pub rst_polarity : ResetPolarity,
+    pub filter : SignalFilter,
+    /// Turn suspicious input (e.g. timestamps going backwards) into errors
+    pub strict : bool,
+    /// Sync on t=0 with a warning when the reset is never released
+    pub allow_no_reset : bool,
+    /// Warn about signals changing more than this many times per 1000
+    /// timestamps, see [`VCD::noisy_signals`]
+    pub noisy_threshold : Option<u64>,
+    /// Drop the signals found by `noisy_threshold` instead of only warning
+    pub drop_noisy : bool,
+    /// Warn about pulses shorter than this many ticks of the trace
+    /// timescale, see [`VCD::glitches`]
+    pub glitch_threshold : Option<u64>,
+    /// Remove the glitches found by `glitch_threshold` instead of only
+    /// warning, except on the reset signal
+    pub filter_glitches : bool,
+    /// Drop the signals without any value change, see
+    /// [`VCD::inactive_signals`]
+    pub drop_inactive : bool,
+    /// The reset signal is a leaf name to look for in every scope, see
+    /// [`find_signal_by_leaf`]
+    pub reset_search : bool,
+    /// Reset signals tried in order when the reset signal isn't found, e.g.
+    /// the name given by another version of the simulator
+    pub reset_fallbacks : Vec<String>,
+    /// Timescale of traces whose header has none, instead of failing
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::cache::serialize_timescale"))]
+    pub default_timescale : Option<(u32, TimescaleUnit)>,
+    /// Width overrides of the signals with these full paths, for widths
+    /// declared wrong by the tool that wrote the trace
+    pub force_width : Vec<(String, u32)>,
+}
+
+/// Dotted path of a signal, e.g. `top.cpu.valid`
+pub fn full_name(scope : &[String], name : &str) -> String
+{
+    scope.iter().map(String::as_str).chain([name]).collect::<Vec<_>>().join(".")
+}
+
+/// How a trace is placed in time relative to the others
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncPoint
+{
+    /// Timestamp at which the reset signal is released, traces are aligned on it
+    ResetSignal(u64),
+    /// Signed shift applied to the trace, in units of the merged timescale
+    ManualOffset(i64),
+    /// The trace starts right after the last event of the traces merged before it
+    Append,
+}
+
+impl fmt::Display for SyncPoint
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            SyncPoint::ResetSignal(rst_end) => write!(f, "reset end at {}", rst_end),
+            SyncPoint::ManualOffset(offset) => write!(f, "offset {}", offset),
+            SyncPoint::Append => write!(f, "appended"),
+        }
+    }
+}
+
+/// What to do with signals having the same full path once traces are merged
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum), value(rename_all = "snake_case"))]
+pub enum NameConflict
+{
+    /// Append the first free `_N` suffix (N >= 2) to the later signals
+    #[default]
+    SuffixIndex,
+    /// Prepend the stem of their input file to the later signals
+    PrefixFile,
+    /// Wrap the signals of every input file in a scope named after the file
+    ScopeFile,
+}
+
+/// Values of the signals at timestamp 0 of a merge, see [`InitStrategy::value`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum), value(rename_all = "snake_case"))]
+pub enum InitStrategy
+{
+    /// Every signal starts at 0
+    #[default]
+    AllZero,
+    /// Every signal starts unknown
+    AllX,
+    /// Every signal starts floating
+    AllZ,
+    /// Registers start unknown, tristates floating and wires at 0
+    ByVarType,
+}
+
+impl InitStrategy
+{
+    /// Initial value of a signal of type `var_type`. By type, `reg` and
+    /// `integer` are x, `tri`, `triand`, `trior` and `trireg` are z,
+    /// `tri1` and `supply1` are 1 and every other type, `wire` included,
+    /// is 0. Reals and strings are always initialized to 0.0 and an empty
+    /// text.
+    pub fn value(self, var_type : VarType) -> Value
+    {
+        match (self, var_type)
+        {
+            (InitStrategy::AllZero, _) => Value::V0,
+            (InitStrategy::AllX, _) => Value::X,
+            (InitStrategy::AllZ, _) => Value::Z,
+            (InitStrategy::ByVarType, VarType::Reg | VarType::Integer) => Value::X,
+            (InitStrategy::ByVarType, VarType::Tri | VarType::TriAnd | VarType::TriOr | VarType::TriReg) => Value::Z,
+            (InitStrategy::ByVarType, VarType::Tri1 | VarType::Supply1) => Value::V1,
+            (InitStrategy::ByVarType, _) => Value::V0,
+        }
+    }
+}
+
+/// An input file of a (merged) trace
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Source
+{
+    pub path : PathBuf,
+    /// Sync point of the file before merging
+    pub sync : SyncPoint,
+    /// Shift applied to the file timestamps by the merges so far
+    pub timeskew : u64,
+    /// Scope the file signals are nested in when written, below the output
+    /// module
+    pub prefix : Option<String>,
+    /// `$dumpoff` / `$dumpon` sections of the file, in file order
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dump_sections : Vec<DumpSection>,
+}
+
+/// A section of an input file where dumping was stopped or resumed
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DumpSection
+{
+    /// Timestamp of the section in the trace, moved along with its changes
+    pub timestamp : u64,
+    pub command : DumpCommand,
+    /// IdCodes of the file variables dumped in the section, but for the
+    /// ones changed again after it at the same timestamp
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::vec_as_str"))]
+    pub codes : Vec<IdCode>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DumpCommand
+{
+    /// `$dumpoff`, the variables of the section go to x
+    Off,
+    /// `$dumpon`, the variables of the section are dumped again
+    On,
+}
+
+impl Source
+{
+    /// Name of the input file without its directory and .vcd / .vcd.gz
+    /// extension
+    pub fn stem(&self) -> String
+    {
+        source_stem(&self.path)
+    }
+}
+
+// Name of an input file without its directory and .vcd / .vcd.gz extension
+fn source_stem(path : &Path) -> String
+{
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.strip_suffix(".vcd").unwrap_or(name).to_string()
+}
+
+/// A parsed trace, ready to be merged with other traces.
+///
+/// `VCD` is `Send`, so several traces can be parsed on separate threads.
+/// With the `serde` feature it can be (de)serialized, see the `cache` module.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VCD
+{
+    pub timescale_value: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::as_str"))]
+    pub timescale_unit : TimescaleUnit,
+    /// Files the trace was parsed from, in merge order
+    pub sources : Vec<Source>,
+    pub signals : Vec<Signal>,
+    pub values : TimestampValues,
+    pub sync : SyncPoint,
+    #[cfg_attr(feature = "serde", serde(with = "crate::cache::option_as_str"))]
+    pub rst_id : Option<IdCode>,
+    pub rst_polarity : ResetPolarity,
+    /// `$version` of the header, merged traces join their different ones
+    pub version : Option<String>,
+    /// `$date` of the header, merged traces join their different ones
+    pub date : Option<String>,
+}
+
+// At most 20 signals are listed by Display
+const DISPLAYED_SIGNALS : usize = 20;
+
+// A change of VCD::values, as its timestamp and index in the changes of it
+type ChangePosition = (u64, usize);
+
+// At most 10 glitches of a trace are listed when parsing it
+const GLITCHES_SHOWN : usize = 10;
+
+// Digits grouped by three with `_`, e.g. 9_999_999
+fn grouped(number : u64) -> String
+{
+    let digits = number.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate()
+    {
+        if i > 0 && (digits.len() - i).is_multiple_of(3)
+        {
+            grouped.push('_');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Summary of the trace and its first signals with their number of changes
+impl fmt::Display for VCD
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "VCD: {} signals, {} {} timescale, ", self.signals.len(), self.timescale_value, self.timescale_unit)?;
+        let to_time = |timestamp : u64| grouped(timestamp.saturating_mul(self.timescale_value as u64));
+        match (self.values.first_key_value(), self.values.last_key_value())
+        {
+            (Some((first, _)), Some((last, _))) => writeln!(f, "T={}..{} {}", to_time(*first), to_time(*last), self.timescale_unit)?,
+            _ => writeln!(f, "no value change")?,
+        }
+
+        let mut changes = vec![0u64; self.signals.len()];
+        for (id, _) in self.values.values().flatten()
+        {
+            changes[*id as usize] += 1;
+        }
+        let shown = &self.signals[..self.signals.len().min(DISPLAYED_SIGNALS)];
+        let rows : Vec<(String, String)> = shown.iter()
+            .map(|signal| (signal.full_name(), format!("[{}, {}b]", signal.var_type, signal.width)))
+            .collect();
+        let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let kind_width = rows.iter().map(|(_, kind)| kind.len()).max().unwrap_or(0);
+        let count_width = changes.iter().take(shown.len()).map(|count| grouped(*count).len()).max().unwrap_or(0);
+        for ((name, kind), count) in rows.iter().zip(&changes)
+        {
+            writeln!(f, "  {:name_width$}  {:kind_width$}  {:>count_width$} changes", name, kind, grouped(*count))?;
+        }
+        if self.signals.len() > shown.len()
+        {
+            writeln!(f, "  ... and {} more", self.signals.len() - shown.len())?;
+        }
+        Ok(())
+    }
+}
+
+// Parsing traces in parallel relies on this
+const _ : fn() = || {
+    fn assert_send<T : Send>() {}
+    assert_send::<VCD>();
+};
+
+impl VCD
+{
+    /// Parses a trace that will be synced on the release of `reset_signal`
+    pub fn new(file_path : &PathBuf, reset_signal : &str, options : &ParseOptions) -> Result<VCD>
+    {
+        VCD::parse(file_path, Some(reset_signal), options)
+    }
+
+    /// Parses a trace that will be shifted by `offset` when merged
+    pub fn with_offset(file_path : &PathBuf, offset : i64, options : &ParseOptions) -> Result<VCD>
+    {
+        Ok(VCD::parse(file_path, None, options)?.synced_on(SyncPoint::ManualOffset(offset)))
+    }
+
+    /// Parses a trace that will be placed after the end of the trace it's merged into
+    pub fn appended(file_path : &PathBuf, options : &ParseOptions) -> Result<VCD>
+    {
+        Ok(VCD::parse(file_path, None, options)?.synced_on(SyncPoint::Append))
+    }
+
+    /// Parses a trace synced on its first scalar change instead of a reset
+    /// signal, for captures without one that all start with the same event
+    /// (e.g. a chip select asserted). Any earlier glitch or initial value
+    /// moves the sync point, prefer [`VCD::new`] when there is a reset.
+    pub fn first_event(file_path : &PathBuf, options : &ParseOptions) -> Result<VCD>
+    {
+        let vcd = VCD::parse(file_path, None, options)?;
+        let first_event = vcd.values.iter()
+            .find(|(_, changes)| changes.iter().any(|(_, value)| matches!(value, StoredValue::Scalar(_))))
+            .map_or(0, |(timestamp, _)| *timestamp);
+        eprintln!("First event found at : {}", display_time(first_event, vcd.timescale_value, vcd.timescale_unit));
+        Ok(vcd.synced_on(SyncPoint::ResetSignal(first_event)))
+    }
+
+    fn synced_on(mut self, sync : SyncPoint) -> VCD
+    {
+        self.sync = sync;
+        for source in self.sources.iter_mut()
+        {
+            source.sync = sync;
+        }
+        self
+    }
+
+    /// Parses a trace held in memory, synced on the release of
+    /// `reset_signal` with the default [`ParseOptions`], e.g. to test on
+    /// inline VCD text without writing it to a file. Messages name it
+    /// `<string>`.
+    ///
+    /// ```
+    /// use vcd_sync::{SyncPoint, VCD};
+    ///
+    /// let vcd = VCD::from_str("$timescale 1 ns $end
+    /// $scope module top $end
+    /// $var wire 1 ! reset $end
+    /// $upscope $end
+    /// $enddefinitions $end
+    /// #0
+    /// 0!
+    /// #10
+    /// 1!
+    /// ", "top.reset").unwrap();
+    /// assert_eq!(vcd.sync, SyncPoint::ResetSignal(10));
+    /// assert_eq!(vcd.signals[0].full_name(), "top.reset");
+    /// ```
+    pub fn from_str(input : &str, reset_signal : &str) -> Result<VCD>
+    {
+        let file_path = PathBuf::from("<string>");
+        let reader = RadixReader::new(ScopeBalancer::new(Cursor::new(input.as_bytes()), &file_path));
+        VCD::parse_from(reader, &file_path, Some(reset_signal), &ParseOptions::default())
+    }
+
+    fn parse(file_path : &PathBuf, reset_signal : Option<&str>, options : &ParseOptions) -> Result<VCD>
+    {
+        VCD::parse_from(open_trace(file_path)?, file_path, reset_signal, options)
+    }
+
+    // `file_path` only names the trace read from `reader`
+    fn parse_from<R : BufRead>(reader : R, file_path : &Path, reset_signal : Option<&str>, options : &ParseOptions) -> Result<VCD>
+    {
+        let mut parser = Parser::new(reader);
+
+        let parsed_header = parser.parse_header()?;
+        let (timescale_value, timescale_unit) = match (parsed_header.timescale, options.default_timescale)
+        {
+            (Some(timescale), _) => timescale,
+            (None, Some((value, unit))) =>
+            {
+                warning!("No timescale in {}, using {} {}", file_path.display(), value, unit);
+                (value, unit)
+            },
+            (None, None) => return Err(VcdSyncError::TimescaleNotFound { file : file_path.to_path_buf() }),
+        };
+        // The first of the reset signal and its fallbacks found in the file
+        let find_reset = |reset_signal : &str| match options.reset_search
+        {
+            true =>
+            {
+                let matches = find_signal_by_leaf(&parsed_header.items, reset_signal);
+                let (full_name, code) = matches.first()?;
+                if matches.len() > 1
+                {
+                    warning!("{} signals are named {} in {}, using {}",
+                             matches.len(), reset_signal, file_path.display(), full_name);
+                }
+                Some(*code)
+            },
+            false =>
+            {
+                let split = reset_signal.split(".").collect::<Vec<&str>>();
+                parsed_header.find_var(&split).map(|var| var.code)
+            },
+        };
+        let (reset_signal, rst_id) = match reset_signal
+        {
+            Some(reset_signal) =>
+            {
+                let tried : Vec<&str> = std::iter::once(reset_signal).chain(options.reset_fallbacks.iter().map(String::as_str)).collect();
+                let (found, code) = tried.iter()
+                    .find_map(|name| Some((*name, find_reset(name)?)))
+                    .ok_or_else(|| match tried.len()
+                    {
+                        1 => VcdSyncError::ResetSignalNotFound { signal : reset_signal.to_string(), file : file_path.to_path_buf() },
+                        _ => VcdSyncError::NoResetSignalFound { tried : tried.join(", "), file : file_path.to_path_buf() },
+                    })?;
+                if found != reset_signal
+                {
+                    eprintln!("Reset signal {} not found in {}, using {}", reset_signal, file_path.display(), found);
+                }
+                (Some(found), Some(code))
+            },
+            None => (None, None),
+        };
+        let mut signals_id = signals(&parsed_header.items);
+        // The reset signal is always kept, it's the sync point of the trace
+        signals_id.retain(|(signal, code)| Some(*code) == rst_id || options.filter.is_selected(&signal.full_name()));
+        for (name, width) in &options.force_width
+        {
+            match signals_id.iter_mut().find(|(signal, _)| signal.full_name() == *name)
+            {
+                Some((signal, _)) => signal.width = *width,
+                None => warning!("Signal {} to force to {} bits not found in {}", name, width, file_path.display()),
+            }
+        }
+        let rst_polarity = options.rst_polarity;
+        let CollectedValues { values, reset_edge : rst_edge, dump_sections, changes } =
+            collect_values(file_path, &signals_id, &mut parser, rst_id, options)?;
+        // Checked before the reset, which is never seen in an empty trace
+        if changes == 0
+        {
+            return Err(VcdSyncError::EmptyFile { file : file_path.to_path_buf() });
+        }
+        if values.values().all(Vec::is_empty)
+        {
+            return Err(VcdSyncError::NoSignalSelected { file : file_path.to_path_buf() });
+        }
+        let rst_end = match (reset_signal, rst_edge)
+        {
+            (None, _) => 0,
+            (Some(_), ResetEdge::Deasserted(rst_end)) =>
+            {
+                eprintln!("Reset signal end found at : {}", display_time(rst_end, timescale_value, timescale_unit));
+                rst_end
+            },
+            (Some(reset_signal), _) if options.allow_no_reset =>
+            {
+                warning!("Reset signal {} is never released in {}, syncing on t=0",
+                         reset_signal, file_path.display());
+                0
+            },
+            (Some(reset_signal), ResetEdge::NeverDeasserted) =>
+                return Err(VcdSyncError::ResetNeverDeasserted { signal : reset_signal.to_string(), file : file_path.to_path_buf() }),
+            (Some(reset_signal), ResetEdge::NotSeen) =>
+                return Err(VcdSyncError::ResetNotSeen { signal : reset_signal.to_string(), file : file_path.to_path_buf() }),
+        };
+        let rst_index = signals_id.iter().position(|(_, code)| Some(*code) == rst_id).map(|index| index as u32);
+        let signals = signals_id.into_iter().map(|(signal, _sig_id)| signal).collect();
+        let sync = SyncPoint::ResetSignal(rst_end);
+        let sources = vec![Source { path : file_path.to_path_buf(), sync, timeskew : 0, prefix : None, dump_sections }];
+        let text = |text : &Option<String>| text.as_deref().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+        let (version, date) = (text(&parsed_header.version), text(&parsed_header.date));
+        let mut vcd = VCD{ timescale_value, timescale_unit, sources, signals, values, rst_id, sync, rst_polarity, version, date };
+
+        if let Some(threshold) = options.noisy_threshold
+        {
+            let mut dropped = HashSet::new();
+            for (id, changes) in vcd.noisy_signals(threshold)
+            {
+                warning!("Signal {} changes {} times in {} timestamps of {}",
+                         vcd.signals[id as usize].full_name(), changes, vcd.values.len(), file_path.display());
+                // Dropping the reset signal would lose the sync point
+                if options.drop_noisy && Some(id) != rst_index
+                {
+                    dropped.insert(id);
+                }
+            }
+            vcd.remove_signals(&dropped);
+        }
+        if options.drop_inactive
+        {
+            let mut inactive = vcd.inactive_signals();
+            // The reset is kept, even a trace never released can be synced
+            if let Some(rst_index) = rst_index
+            {
+                inactive.remove(&rst_index);
+            }
+            if !inactive.is_empty()
+            {
+                eprintln!("Dropped {} signals without any value change from {}", inactive.len(), file_path.display());
+            }
+            vcd.remove_signals(&inactive);
+        }
+        if let Some(threshold) = options.glitch_threshold
+        {
+            let glitches = vcd.glitches(threshold);
+            if !glitches.is_empty()
+            {
+                let time = |ticks| display_time(ticks, vcd.timescale_value, vcd.timescale_unit);
+                warning!("{} glitches shorter than {} in {}", glitches.len(), time(threshold), file_path.display());
+                for glitch in glitches.iter().take(GLITCHES_SHOWN)
+                {
+                    eprintln!("  {} at {}, {} wide", vcd.signals[glitch.signal as usize].full_name(), time(glitch.timestamp), time(glitch.width));
+                }
+                if glitches.len() > GLITCHES_SHOWN
+                {
+                    eprintln!("  and {} more", glitches.len() - GLITCHES_SHOWN);
+                }
+            }
+            if options.filter_glitches
+            {
+                // Filtering the reset would move the sync point
+                let filtered = vcd.filter_glitches(threshold, rst_index);
+                if filtered > 0
+                {
+                    eprintln!("Filtered {} glitches from {}", filtered, file_path.display());
+                }
+            }
+        }
+        Ok(vcd)
+    }
+
+    /// Pulses shorter than `threshold` ticks: changes of a signal going
+    /// back to its previous value that soon after, in time order. Changes
+    /// of a pulse at the same timestamp make a 0 wide glitch.
+    pub fn glitches(&self, threshold : u64) -> Vec<Glitch>
+    {
+        self.glitch_changes(threshold).into_iter().map(|(glitch, _, _)| glitch).collect()
+    }
+
+    // Glitches with the changes starting and ending them
+    fn glitch_changes(&self, threshold : u64) -> Vec<(Glitch, ChangePosition, ChangePosition)>
+    {
+        // Per signal, the value before the current one, the current value
+        // and where it was set. A glitch ends a pulse, the next one can't
+        // start before it
+        let mut before : Vec<Option<&StoredValue>> = vec![None; self.signals.len()];
+        let mut current : Vec<Option<(&StoredValue, ChangePosition)>> = vec![None; self.signals.len()];
+        let mut glitches = Vec::new();
+        for (timestamp, changes) in &self.values
+        {
+            for (index, (id, value)) in changes.iter().enumerate()
+            {
+                let (before, current) = (&mut before[*id as usize], &mut current[*id as usize]);
+                match *current
+                {
+                    Some((current_value, _)) if current_value == value => continue,
+                    Some((_, (since, since_index))) if *before == Some(value) && timestamp - since < threshold =>
+                    {
+                        glitches.push((Glitch { signal : *id, timestamp : since, width : timestamp - since },
+                                       (since, since_index), (*timestamp, index)));
+                        *before = None;
+                    },
+                    Some((current_value, _)) => *before = Some(current_value),
+                    None => (),
+                }
+                *current = Some((value, (*timestamp, index)));
+            }
+        }
+        glitches.sort_by_key(|(glitch, _, _)| (glitch.timestamp, glitch.signal));
+        glitches
+    }
+
+    /// Removes the changes of the [`VCD::glitches`] of every signal but
+    /// `except` (e.g. the reset), returns the number of glitches removed
+    pub fn filter_glitches(&mut self, threshold : u64, except : Option<u32>) -> usize
+    {
+        let mut removed = HashSet::new();
+        let mut count = 0;
+        for (glitch, start, end) in self.glitch_changes(threshold)
+        {
+            if Some(glitch.signal) != except
+            {
+                removed.insert(start);
+                removed.insert(end);
+                count += 1;
+            }
+        }
+        for (timestamp, changes) in self.values.iter_mut()
+        {
+            let mut index = 0;
+            changes.retain(|_| { index += 1; !removed.contains(&(*timestamp, index - 1)) });
+        }
+        prune_empty_timestamps(&mut self.values);
+        count
+    }
+
+    /// Signals declared in the header that never change, some tools declare
+    /// far more variables than they dump
+    pub fn inactive_signals(&self) -> HashSet<u32>
+    {
+        let mut active = vec![false; self.signals.len()];
+        for (id, _) in self.values.values().flatten()
+        {
+            active[*id as usize] = true;
+        }
+        active.into_iter().enumerate().filter(|(_, active)| !active).map(|(id, _)| id as u32).collect()
+    }
+
+    /// How many signals have at least one value change
+    pub fn active_signal_count(&self) -> usize
+    {
+        self.signals.len() - self.inactive_signals().len()
+    }
+
+    /// Signals changing more than `threshold` times per 1000 timestamps of
+    /// the trace, with their number of changes. These are usually glitchy
+    /// captures that bloat the output.
+    pub fn noisy_signals(&self, threshold : u64) -> Vec<(u32, u64)>
+    {
+        let mut counts = vec![0u64; self.signals.len()];
+        for (id, _) in self.values.values().flatten()
+        {
+            counts[*id as usize] += 1;
+        }
+        let timestamps = self.values.len() as u64;
+        counts.into_iter().enumerate()
+            .filter(|(_, changes)| changes * 1000 > threshold * timestamps)
+            .map(|(id, changes)| (id as u32, changes))
+            .collect()
+    }
+
+    /// Signals holding `state`, as a scalar or as any bit of a vector, with
+    /// the first timestamp they do. X or Z usually tells about an
+    /// uninitialized register or a bus driven twice.
+    pub fn first_timestamps_with(&self, state : Value) -> Vec<(u32, u64)>
+    {
+        let mut first = vec![None; self.signals.len()];
+        for (timestamp, changes) in &self.values
+        {
+            for (id, value) in changes
+            {
+                let holds = match value
+                {
+                    StoredValue::Scalar(value) => *value == state,
+                    StoredValue::Vector(vector) => vector.iter().any(|bit| bit == state),
+                    StoredValue::Real(_) | StoredValue::Str(_) => false,
+                };
+                if holds
+                {
+                    first[*id as usize].get_or_insert(*timestamp);
+                }
+            }
+        }
+        first.into_iter().enumerate()
+            .filter_map(|(id, timestamp)| Some((id as u32, timestamp?)))
+            .collect()
+    }
+
+    /// Removes these signals and all their changes, the remaining signals
+    /// are renumbered in order
+    pub fn remove_signals(&mut self, ids : &HashSet<u32>)
+    {
+        if ids.is_empty()
+        {
+            return;
+        }
+        let mut new_ids = Vec::with_capacity(self.signals.len());
+        let mut signals = Vec::new();
+        for (id, signal) in std::mem::take(&mut self.signals).into_iter().enumerate()
+        {
+            match ids.contains(&(id as u32))
+            {
+                true => new_ids.push(None),
+                false =>
+                {
+                    new_ids.push(Some(signals.len() as u32));
+                    signals.push(signal);
+                },
+            }
+        }
+        self.signals = signals;
+        for changes in self.values.values_mut()
+        {
+            changes.retain_mut(|(id, _)| match new_ids[*id as usize]
+            {
+                Some(new_id) => { *id = new_id; true },
+                None => false,
+            });
+        }
+        self.values.retain(|_, changes| !changes.is_empty());
+    }
+
+    /// Orders the signals by full path, then index, and the changes of each
+    /// timestamp by signal, so that traces differing only by the order of
+    /// their declarations are written the same
+    pub fn sort_signals(&mut self)
+    {
+        let new_ids = sorted_ids(&self.signals);
+        let mut signals : Vec<(u32, Signal)> = new_ids.iter().copied().zip(std::mem::take(&mut self.signals)).collect();
+        signals.sort_by_key(|(new_id, _)| *new_id);
+        self.signals = signals.into_iter().map(|(_, signal)| signal).collect();
+        for changes in self.values.values_mut()
+        {
+            renumber_changes(changes, &new_ids);
+        }
+    }
+
+    /// Rough size in bytes of the trace once written as VCD
+    pub fn estimated_vcd_size(&self) -> u64
+    {
+        // IdCodes use 94 printable characters
+        let id_len = (self.signals.len().max(1) as f64).log(94.0).floor() as u64 + 1;
+        let header : u64 = self.signals.iter()
+            .map(|signal| 24 + signal.name.len() as u64 + signal.width.max(1).ilog10() as u64 + id_len
+                 + signal.scope.iter().map(|s| 30 + s.len() as u64).sum::<u64>())
+            .sum();
+        let body : u64 = self.values.iter()
+            .map(|(timestamp, changes)| 2 + timestamp.checked_ilog10().unwrap_or(0) as u64 + 1
+                 + changes.iter().map(|(_, value)| match value
+                 {
+                     StoredValue::Scalar(_) => 2 + id_len,
+                     StoredValue::Vector(vector) => 3 + vector.len() as u64 + id_len,
+                     StoredValue::Real(value) => 3 + value.to_string().len() as u64 + id_len,
+                     StoredValue::Str(value) => 3 + value.len() as u64 + id_len,
+                 }).sum::<u64>())
+            .sum();
+        header + body
+    }
+
+    /// Index in `signals` of the first signal with this full dotted path
+    pub fn signal_id(&self, full_name : &str) -> Option<u32>
+    {
+        self.signals.iter().position(|signal| signal.full_name() == full_name).map(|id| id as u32)
+    }
+
+    /// Value of the signal with this full dotted path at `timestamp`, `None`
+    /// when it doesn't exist or hasn't changed yet
+    pub fn value_at(&self, signal : &str, timestamp : u64) -> Option<StoredValue>
+    {
+        let id = self.signal_id(signal)?;
+        // Latest change of the signal, the last one wins within a timestamp
+        self.values.range(..=timestamp)
+            .rev()
+            .find_map(|(_, changes)| changes.iter().rev().find(|(change_id, _)| *change_id == id))
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Full paths declared by both `self` and `other`, the signals a merge
+    /// renames with [`VCD::resolve_name_conflicts`]
+    pub fn common_signals<'a>(&'a self, other : &'a VCD) -> impl Iterator<Item = String> + 'a
+    {
+        let names = other.full_names();
+        self.signals.iter().map(Signal::full_name).filter(move |name| names.contains(name))
+    }
+
+    /// Full paths declared by `self` but not `other`
+    pub fn unique_to_self<'a>(&'a self, other : &'a VCD) -> impl Iterator<Item = String> + 'a
+    {
+        let names = other.full_names();
+        self.signals.iter().map(Signal::full_name).filter(move |name| !names.contains(name))
+    }
+
+    /// Full paths declared by `other` but not `self`
+    pub fn unique_to_other<'a>(&'a self, other : &'a VCD) -> impl Iterator<Item = String> + 'a
+    {
+        other.unique_to_self(self)
+    }
+
+    fn full_names(&self) -> HashSet<String>
+    {
+        self.signals.iter().map(Signal::full_name).collect()
+    }
+
+    /// Every value change as `(timestamp, signal name, value)`, in time
+    /// order. Names are the leaf names, use [`Signal::full_name`] on
+    /// `signals` to tell apart signals of different scopes.
+    pub fn changes(&self) -> impl Iterator<Item = (u64, &str, &StoredValue)>
+    {
+        self.values.iter().flat_map(move |(timestamp, changes)| changes.iter()
+            .map(move |(id, value)| (*timestamp, self.signals[*id as usize].name.as_str(), value)))
+    }
+
+    /// Smallest interval between two rising edges of the scalar signal with
+    /// this full dotted path, `None` if it doesn't exist or has less than
+    /// two rising edges
+    pub fn clock_period(&self, signal : &str) -> Option<u64>
+    {
+        self.rising_edges(signal)?.windows(2)
+            .map(|edges| edges[1] - edges[0])
+            .filter(|interval| *interval > 0)
+            .min()
+    }
+
+    /// The 1 bit signal changing the most, most likely the clock of the
+    /// trace, the first declared one on a tie
+    pub fn infer_clock(&self) -> Option<&Signal>
+    {
+        let mut counts = vec![0u64; self.signals.len()];
+        for (id, value) in self.values.values().flatten()
+        {
+            if let StoredValue::Scalar(_) = value
+            {
+                counts[*id as usize] += 1;
+            }
+        }
+        let (id, _) = counts.iter().enumerate()
+            .filter(|(id, changes)| self.signals[*id].width == 1 && **changes > 0)
+            .rev()
+            .max_by_key(|(_, changes)| **changes)?;
+        Some(&self.signals[id])
+    }
+
+    /// Timestamps at which the scalar signal with this full dotted path goes
+    /// to 1 from another value, like a reset being released, `None` if it
+    /// doesn't exist
+    pub fn rising_edges(&self, signal : &str) -> Option<Vec<u64>>
+    {
+        let id = self.signal_id(signal)?;
+        let mut previous = None;
+        let mut edges = Vec::new();
+        for (timestamp, changes) in &self.values
+        {
+            for (_, value) in changes.iter().filter(|(change_id, _)| *change_id == id)
+            {
+                let high = *value == StoredValue::Scalar(Value::V1);
+                if high && previous.is_some_and(|previous| previous != value)
+                {
+                    edges.push(*timestamp);
+                }
+                previous = Some(value);
+            }
+        }
+        Some(edges)
+    }
+
+    /// Moves the changes of every signal but `clock` to the nearest rising
+    /// edge of `clock` (the earlier one on a tie), which drops the sub-cycle
+    /// jitter between traces. Changes before its first edge, like the initial
+    /// state, are left where they are. Returns the number of changes moved, `None`
+    /// when `clock` doesn't exist or never rises.
+    pub fn clock_align(&mut self, clock : &str) -> Option<usize>
+    {
+        let id = self.signal_id(clock)?;
+        let edges = self.rising_edges(clock).filter(|edges| !edges.is_empty())?;
+        let nearest = |timestamp : u64| match edges.binary_search(&timestamp)
+        {
+            Ok(_) | Err(0) => timestamp,
+            Err(i) if i == edges.len() => edges[i - 1],
+            Err(i) if timestamp - edges[i - 1] <= edges[i] - timestamp => edges[i - 1],
+            Err(i) => edges[i],
+        };
+
+        let mut moved = 0;
+        let mut aligned = TimestampValues::new();
+        for (timestamp, changes) in std::mem::take(&mut self.values)
+        {
+            for (change_id, value) in changes
+            {
+                let target = match change_id == id
+                {
+                    true => timestamp,
+                    false => nearest(timestamp),
+                };
+                moved += usize::from(target != timestamp);
+                aligned.entry(target).or_default().push((change_id, value));
+            }
+        }
+        self.values = aligned;
+        Some(moved)
+    }
+
+    /// Value of every signal at `timestamp`, changes happening at `timestamp`
+    /// included. Signals that haven't changed yet have no entry.
+    pub fn last_values_at(&self, timestamp : u64) -> HashMap<u32, StoredValue>
+    {
+        last_values_at(&self.values, timestamp)
+    }
+
+    /// Samples the trace every `period` ticks, from 0 to the first sample
+    /// after its last change, a `period` of 0 is taken as 1.
+    ///
+    /// The state of each signal at a sample is the one
+    /// [`VCD::last_values_at`] gives, only the signals whose state changed
+    /// since the previous sample are written there, and samples without
+    /// any change are skipped. Transitions between two samples are lost.
+    pub fn resample(&self, period : u64) -> VCD
+    {
+        // Writes the signals whose state differs from the one last sampled
+        fn sample<'a>(values : &mut TimestampValues, timestamp : u64, state : &[Option<&'a StoredValue>], sampled : &mut [Option<&'a StoredValue>])
+        {
+            let mut changes = Vec::new();
+            for (id, (value, sampled)) in state.iter().zip(sampled.iter_mut()).enumerate()
+            {
+                if let Some(value) = value.filter(|value| *sampled != Some(*value))
+                {
+                    *sampled = Some(value);
+                    changes.push((id as u32, value.clone()));
+                }
+            }
+            if !changes.is_empty()
+            {
+                values.insert(timestamp, changes);
+            }
+        }
+
+        let period = period.max(1);
+        let mut state : Vec<Option<&StoredValue>> = vec![None; self.signals.len()];
+        let mut sampled : Vec<Option<&StoredValue>> = vec![None; self.signals.len()];
+        let mut values = TimestampValues::new();
+        // The state only changes at a timestamp of the trace, the first
+        // sample after each one is enough
+        let mut next_sample = 0;
+        for (timestamp, changes) in &self.values
+        {
+            if *timestamp > next_sample
+            {
+                sample(&mut values, next_sample, &state, &mut sampled);
+                next_sample = timestamp.div_ceil(period) * period;
+            }
+            for (id, value) in changes
+            {
+                state[*id as usize] = Some(value);
+            }
+        }
+        sample(&mut values, next_sample, &state, &mut sampled);
+        // A dump section lands on the first sample after it
+        let mut sources = self.sources.clone();
+        for section in sources.iter_mut().flat_map(|source| source.dump_sections.iter_mut())
+        {
+            section.timestamp = section.timestamp.div_ceil(period) * period;
+        }
+
+        VCD {
+            timescale_value : self.timescale_value,
+            timescale_unit : self.timescale_unit,
+            sources,
+            signals : self.signals.clone(),
+            values,
+            rst_id : self.rst_id,
+            sync : self.sync,
+            rst_polarity : self.rst_polarity,
+            version : self.version.clone(),
+            date : self.date.clone(),
+        }
+    }
+
+    /// Drops every timestamp outside `[start, end]` and moves `start` to 0.
+    ///
+    /// The state of every signal at `start` is written at the new 0, so the
+    /// window starts from a known state. Returns the number of timestamps
+    /// removed. A reset end before the window is clamped to 0.
+    pub fn trim_to_window(&mut self, start : u64, end : u64) -> u64
+    {
+        let kept = match start <= end
+        {
+            true => self.values.range(start..=end).count(),
+            false => 0,
+        };
+        let removed = (self.values.len() - kept) as u64;
+
+        self.values = window_values(std::mem::take(&mut self.values), start, end)
+            .into_iter()
+            .map(|(timestamp, changes)| (timestamp - start, changes))
+            .collect();
+        for source in self.sources.iter_mut()
+        {
+            source.dump_sections.retain(|section| (start..=end).contains(&section.timestamp));
+            for section in source.dump_sections.iter_mut()
+            {
+                section.timestamp -= start;
+            }
+        }
+        self.sync = match self.sync
+        {
+            SyncPoint::ResetSignal(rst_end) => SyncPoint::ResetSignal(rst_end.saturating_sub(start)),
+            SyncPoint::ManualOffset(offset) => SyncPoint::ManualOffset(offset.saturating_add_unsigned(start)),
+            SyncPoint::Append => SyncPoint::Append,
+        };
+        removed
+    }
+
+    /// Splits a merge of two traces synced on their reset back into them.
+    ///
+    /// Signals are given to the first trace when their full path starts
+    /// with the `prefix_a` scopes, else to the second one when it starts
+    /// with `prefix_b`, and lose these scopes, e.g. the file scopes added by
+    /// [`NameConflict::ScopeFile`]. Each trace is moved back by
+    /// the timeskew its reset end `rst_end_a` / `rst_end_b` got from
+    /// [`VCD::merge`], the changes it then has before 0 are dropped.
+    pub fn split_at(&self, rst_end_a : u64, rst_end_b : u64, prefix_a : &str, prefix_b : &str) -> (VCD, VCD)
+    {
+        let rst_end = rst_end_a.max(rst_end_b);
+        let sides = [(prefix_a, rst_end_a), (prefix_b, rst_end_b)];
+        // Merged signal id -> (side, id in that side)
+        let mut ids = vec![None; self.signals.len()];
+        let mut split = sides.map(|(_, side_rst_end)| VCD {
+            timescale_value : self.timescale_value,
+            timescale_unit : self.timescale_unit,
+            sources : Vec::new(),
+            signals : Vec::new(),
+            values : TimestampValues::new(),
+            sync : SyncPoint::ResetSignal(side_rst_end),
+            rst_id : None,
+            rst_polarity : self.rst_polarity,
+            version : self.version.clone(),
+            date : self.date.clone(),
+        });
+        // Merged source index -> index in the side it went to
+        let mut sources = vec![None; self.sources.len()];
+
+        for (id, signal) in self.signals.iter().enumerate()
+        {
+            let Some((side, depth)) = sides.iter().enumerate().find_map(|(side, (prefix, _))|
+            {
+                let prefix : Vec<&str> = prefix.split('.').filter(|scope| !scope.is_empty()).collect();
+                let matched = prefix.len() <= signal.scope.len() && signal.scope.iter().zip(&prefix).all(|(scope, prefix)| scope == prefix);
+                matched.then_some((side, prefix.len()))
+            }) else { continue };
+            let vcd = &mut split[side];
+            let timeskew = rst_end - sides[side].1;
+            let source = *sources[signal.source].get_or_insert_with(||
+            {
+                let source = &self.sources[signal.source];
+                vcd.sources.push(Source {
+                    path : source.path.clone(),
+                    sync : source.sync,
+                    timeskew : source.timeskew.saturating_sub(timeskew),
+                    prefix : source.prefix.clone(),
+                    dump_sections : source.dump_sections.iter()
+                        .filter_map(|section| Some(DumpSection { timestamp : section.timestamp.checked_sub(timeskew)?, ..section.clone() }))
+                        .collect(),
+                });
+                vcd.sources.len() - 1
+            });
+            ids[id] = Some((side, vcd.signals.len() as u32));
+            vcd.signals.push(Signal { scope : signal.scope[depth..].to_vec(), source, ..signal.clone() });
+        }
+
+        for (timestamp, changes) in &self.values
+        {
+            for (id, value) in changes
+            {
+                let Some((side, side_id)) = ids[*id as usize] else { continue };
+                if let Some(timestamp) = timestamp.checked_sub(rst_end - sides[side].1)
+                {
+                    split[side].values.entry(timestamp).or_default().push((side_id, value.clone()));
+                }
+            }
+        }
+        let [a, b] = split;
+        (a, b)
+    }
+
+    /// Renames signals so that no two of them share the same full path.
+    ///
+    /// Signals of a source with a prefix are first nested in a scope named
+    /// after it, below the `top` module: `top.cpu.clk` and `cpu.clk` both
+    /// become `top.board_a.cpu.clk` with a `board_a` prefix. `ScopeFile`
+    /// uses the prefix instead of the file name. The first signal with a
+    /// given path always keeps its name.
+    pub fn resolve_name_conflicts(&mut self, strategy : NameConflict)
+    {
+        self.resolve_renamed_conflicts(strategy, &[], "top");
+    }
+
+    /// Like [`VCD::resolve_name_conflicts`], with the prefixes nested in
+    /// `module_name` and the first scope named `old` of every signal path
+    /// renamed to `new` once the prefixes are added, so that conflicts are
+    /// looked for in the renamed hierarchy. Returns the `old` names found in
+    /// no path.
+    pub fn resolve_renamed_conflicts(&mut self, strategy : NameConflict, scope_renames : &[(String, String)], module_name : &str) -> Vec<String>
+    {
+        for signal in self.signals.iter_mut()
+        {
+            let source = &self.sources[signal.source];
+            match &source.prefix
+            {
+                Some(prefix) =>
+                {
+                    // Signals already in the module get the prefix right below it
+                    if signal.scope.first().map(String::as_str) != Some(module_name)
+                    {
+                        signal.scope.insert(0, module_name.to_string());
+                    }
+                    signal.scope.insert(1, prefix.clone());
+                },
+                None if strategy == NameConflict::ScopeFile => signal.scope.insert(0, source_stem(&source.path)),
+                None => (),
+            }
+        }
+
+        let mut unmatched = Vec::new();
+        for (old, new) in scope_renames
+        {
+            let mut matched = false;
+            for signal in self.signals.iter_mut()
+            {
+                if let Some(scope) = signal.scope.iter_mut().find(|scope| *scope == old)
+                {
+                    scope.clone_from(new);
+                    matched = true;
+                }
+            }
+            if !matched
+            {
+                unmatched.push(old.clone());
+            }
+        }
+
+        // Every original path is reserved so that a generated name can't
+        // collide with a signal coming later, e.g. an existing clk_2. Array
+        // elements only differ by their index.
+        let key = |signal : &Signal, name : &str| (signal.scope.clone(), name.to_string(), signal.index.map(|index| index.to_string()));
+        let reserved : HashSet<_> = self.signals.iter()
+            .map(|signal| key(signal, &signal.name))
+            .collect();
+        let mut used = HashSet::new();
+        for signal in self.signals.iter_mut()
+        {
+            if used.insert(key(signal, &signal.name))
+            {
+                continue;
+            }
+
+            let is_free = |name : &str| {
+                let key = key(signal, name);
+                !reserved.contains(&key) && !used.contains(&key)
+            };
+            let mut name = signal.name.clone();
+            if strategy == NameConflict::PrefixFile
+            {
+                name = format!("{}_{}", source_stem(&self.sources[signal.source].path), signal.name);
+            }
+            let base_name = name.clone();
+            let mut index = 2;
+            while !is_free(&name)
+            {
+                name = format!("{}_{}", base_name, index);
+                index += 1;
+            }
+            used.insert(key(signal, &name));
+            signal.name = name;
+        }
+        unmatched
+    }
+
+    /// Delays every event of the trace by `shift`, e.g. its timeskew from
+    /// [`rounded_timeskews`], which is recorded in its sources
+    pub fn shift(&mut self, shift : u64) -> Result<()>
+    {
+        if shift > 0
+        {
+            self.shift_sources(shift)?;
+            self.values = std::mem::take(&mut self.values)
+                .into_iter()
+                .map(|(timestamp, values)| (timestamp + shift, values))
+                .collect();
+        }
+        Ok(())
+    }
+
+    // Records a delay of `shift` in the sources, once checked that it
+    // overflows no timestamp
+    fn shift_sources(&mut self, shift : u64) -> Result<()>
+    {
+        // Only the last timestamp can overflow
+        if let Some((&timestamp, _)) = self.values.last_key_value()
+        {
+            timestamp.checked_add(shift).ok_or(VcdSyncError::ShiftOverflow { timestamp, timeskew : shift })?;
+        }
+        for source in self.sources.iter_mut()
+        {
+            source.timeskew += shift;
+            for section in source.dump_sections.iter_mut()
+            {
+                section.timestamp += shift;
+            }
+        }
+        Ok(())
+    }
+
+    /// Folds `vcd` into this trace, both ending up aligned on their sync points
+    pub fn merge(&mut self, vcd : VCD) -> Result<()>
+    {
+        self.merge_rounded(vcd, 1)
+    }
+
+    /// Like [`VCD::merge`], with the timeskew between two traces synced on
+    /// their reset rounded up to a multiple of `period`, e.g. a clock period
+    /// from [`VCD::clock_period`] so that no sub-cycle skew is introduced
+    pub fn merge_rounded(&mut self, vcd : VCD, period : u64) -> Result<()>
+    {
+        self.merge_synced(vcd, period)?;
+
+        // Initialize all signals to 0 at timestamp 0 to avoid errors in GTKWavee
+        let init = self.initial_values(|_| Value::V0);
+        self.values.insert(0, init);
+        Ok(())
+    }
+
+    // Like VCD::merge, with the changes of both traces at timestamp 0 kept
+    // after the initialization instead of replaced by it, so that their
+    // starting states can be compared
+    pub(crate) fn merge_keeping_start(&mut self, vcd : VCD) -> Result<()>
+    {
+        self.merge_synced(vcd, 1)?;
+        let mut init = self.initial_values(|_| Value::V0);
+        init.extend(self.values.remove(&0).unwrap_or_default());
+        self.values.insert(0, init);
+        Ok(())
+    }
+
+    // Merge of the values of both traces once synced, nothing initialized
+    fn merge_synced(&mut self, mut vcd : VCD, period : u64) -> Result<()>
+    {
+        let shifts = self.merge_timeskew(&mut vcd, period)?;
+        // vcd first, so that self is left untouched if either overflows
+        vcd.shift(shifts.vcd_shift)?;
+        self.shift(shifts.self_shift)?;
+        let signals_id_start = self.append_header(&mut vcd, shifts.sync);
+
+        // Merge the already synced values
+        for (timestamp, values) in vcd.values.into_iter()
+        {
+            let entry = self.values.entry(timestamp).or_default();
+            for (id, value) in values
+            {
+                entry.push((id + signals_id_start, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges `other` into this trace like [`VCD::merge`] and writes the
+    /// result to `writer` as it goes, like [`write_vcd_to_writer`] with the
+    /// default [`WriteOptions`].
+    ///
+    /// The merged values are never collected: both traces are walked in
+    /// timestamp order and each timestamp is written as soon as it is
+    /// merged, so no more than two timestamps of events are held on top of
+    /// the inputs.
+    pub fn merge_stream<W : Write>(self, other : VCD, writer : W) -> Result<()>
+    {
+        self.merge_stream_with(other, 1, |_| Value::V0, writer, &WriteOptions::default())
+    }
+
+    /// Like [`VCD::merge_stream`], with the timeskew rounded up to a multiple
+    /// of `period` like [`VCD::merge_rounded`] does, every signal set to
+    /// `init` of its type at timestamp 0 like [`VCD::initialize_with`] and
+    /// the output
+    /// written with `options`. Trimming only keeps the last value of each
+    /// signal until the trim start.
+    pub fn merge_stream_with<W : Write>(mut self, mut other : VCD, period : u64, init : impl Fn(VarType) -> Value, writer : W, options : &WriteOptions) -> Result<()>
+    {
+        let shifts = self.merge_timeskew(&mut other, period)?;
+        other.shift_sources(shifts.vcd_shift)?;
+        self.shift_sources(shifts.self_shift)?;
+        let self_values = std::mem::take(&mut self.values);
+        let other_values = std::mem::take(&mut other.values);
+        let signals_id_start = self.append_header(&mut other, shifts.sync);
+
+        prepare_names(&mut self, options);
+        // The values aren't there yet, they are renumbered as they are merged
+        let new_ids = options.sort_signals.then(||
+        {
+            let new_ids = sorted_ids(&self.signals);
+            self.sort_signals();
+            new_ids
+        });
+        let inverted_ids = inverted_ids(&self, options);
+        let (mut writer, signals_map) = write_header(&self, writer, options)?;
+
+        let mut self_values = self_values.into_iter()
+            .map(|(timestamp, changes)| (timestamp + shifts.self_shift, changes))
+            .peekable();
+        let mut other_values = other_values.into_iter()
+            .map(|(timestamp, changes)| (timestamp + shifts.vcd_shift,
+                                         changes.into_iter().map(|(id, value)| (id + signals_id_start, value)).collect::<Vec<_>>()))
+            .peekable();
+        let merged = std::iter::from_fn(||
+        {
+            let order = match (self_values.peek(), other_values.peek())
+            {
+                (Some((timestamp, _)), Some((other_timestamp, _))) => timestamp.cmp(other_timestamp),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, _) => std::cmp::Ordering::Greater,
+            };
+            match order
+            {
+                std::cmp::Ordering::Less => self_values.next(),
+                std::cmp::Ordering::Greater => other_values.next(),
+                std::cmp::Ordering::Equal =>
+                {
+                    let (timestamp, mut changes) = self_values.next()?;
+                    changes.extend(other_values.next()?.1);
+                    Some((timestamp, changes))
+                },
+            }
+        });
+
+        // Same as merge, timestamp 0 is replaced by every signal at `init`
+        let mut duplicates = 0;
+        let mut seen = HashSet::new();
+        let values = std::iter::once((0, self.initial_values(init)))
+            .chain(merged.filter(|(timestamp, _)| *timestamp != 0).map(|(timestamp, mut changes)|
+            {
+                if let Some(new_ids) = &new_ids
+                {
+                    renumber_changes(&mut changes, new_ids);
+                }
+                (timestamp, changes)
+            }))
+            .map(|(timestamp, mut changes)|
+            {
+                duplicates += dedup_changes(&mut changes, &mut seen);
+                for (_, value) in changes.iter_mut().filter(|(id, _)| inverted_ids.contains(id))
+                {
+                    *value = inverted(value);
+                }
+                (timestamp, changes)
+            });
+        let comment_timescale = options.timestamp_comments.then_some((self.timescale_value, self.timescale_unit));
+        write_body(&mut writer, &self.sources, &self.signals, &signals_map, trim_stream(values, options), comment_timescale)?;
+        if duplicates > 0
+        {
+            warning!("Dropped {} value changes overridden at the same timestamp", duplicates);
+        }
+        Ok(())
+    }
+
+    // Brings `vcd` to the timescale of this trace, then reports and returns
+    // the shifts aligning their sync points
+    fn merge_timeskew(&mut self, vcd : &mut VCD, period : u64) -> Result<MergeShifts>
+    {
+        normalize_timescale(self, vcd)?;
+        if self.rst_polarity != vcd.rst_polarity
+        {
+            warning!("Merging traces synced on different reset polarities ({:?} and {:?})",
+                     self.rst_polarity, vcd.rst_polarity);
+        }
+
+        let self_end = self.values.last_key_value().map(|(timestamp, _)| *timestamp);
+        let shifts = merge_shifts(self.sync, self_end, vcd.sync, period)?;
+        // One of the two shifts is always 0
+        let time = |timestamp| display_time(timestamp, self.timescale_value, self.timescale_unit);
+        match shifts.self_shift.max(shifts.vcd_shift)
+        {
+            timeskew if timeskew != shifts.raw_timeskew =>
+                eprintln!("Merging files with a timeskew of {} (rounded up from {})", time(timeskew), time(shifts.raw_timeskew)),
+            timeskew => eprintln!("Merging files with a timeskew of {}", time(timeskew)),
+        }
+        if shifts.self_shift > 0
+        {
+            eprintln!("Shifting reference trace forward");
+        }
+        Ok(shifts)
+    }
+
+    // Moves the sources and signals of `vcd` after the ones of this trace,
+    // returns the id of its first signal
+    fn append_header(&mut self, vcd : &mut VCD, sync : SyncPoint) -> u32
+    {
+        self.sync = sync;
+        self.version = joined_text(self.version.take(), vcd.version.take());
+        self.date = joined_text(self.date.take(), vcd.date.take());
+
+        let signals_id_start = self.signals.len() as u32;
+        //XXX we should remove all 'none' signals
+        //created by acquisiton tool
+
+        // Duplicate names are kept here, see resolve_name_conflicts
+        let sources_start = self.sources.len();
+        self.sources.append(&mut vcd.sources);
+        for mut vcd_signal in vcd.signals.drain(..)
+        {
+            vcd_signal.source += sources_start;
+            self.signals.push(vcd_signal);
+        }
+        signals_id_start
+    }
+
+    /// Replaces the initialization of a merge at timestamp 0, every signal
+    /// at 0, by every signal at `value`, e.g. `Value::X` for flows where an
+    /// unknown state shouldn't be assumed to be 0. Reals are still
+    /// initialized to 0.0 and strings to an empty text.
+    pub fn initialize_to(&mut self, value : Value)
+    {
+        self.initialize_with(|_| value);
+    }
+
+    /// Like [`VCD::initialize_to`], with the value of each signal given by
+    /// its type, e.g. `|var_type| InitStrategy::ByVarType.value(var_type)`
+    pub fn initialize_with(&mut self, init : impl Fn(VarType) -> Value)
+    {
+        let init = self.initial_values(init);
+        self.values.insert(0, init);
+    }
+
+    // Every signal at `init` of its type, or 0.0 for reals and an empty text
+    // for strings
+    fn initial_values(&self, init : impl Fn(VarType) -> Value) -> Vec<(u32, StoredValue)>
+    {
+        let mut init_values = Vec::new();
+        for (id, signal) in self.signals.iter().enumerate()
+        {
+            let value = match (signal.var_type, signal.width)
+            {
+                (VarType::Real, _) => StoredValue::Real(0.0),
+                (VarType::String, _) => StoredValue::Str(String::new()),
+                (var_type, 1) => StoredValue::Scalar(init(var_type)),
+                (var_type, width) => StoredValue::Vector(Vector::filled(init(var_type), width as usize)),
+            };
+            init_values.push((id as u32, value));
+        }
+        init_values
+    }
+}
+
+// Header text of a merge, `a | b` when both traces have a different one
+fn joined_text(text : Option<String>, other : Option<String>) -> Option<String>
+{
+    match (text, other)
+    {
+        (Some(text), Some(other)) if text != other && !text.split(" | ").any(|part| part == other) => Some(format!("{} | {}", text, other)),
+        (text, other) => text.or(other),
+    }
+}
+
+// Gzip streams start with these two magic bytes
+const GZIP_MAGIC : [u8; 2] = [0x1f, 0x8b];
+
+// Opens a trace file, transparently decompressing it if it's gzipped, with
+// its octal and hexadecimal changes read as binary and its header scopes
+// balanced
+fn open_trace(file_path : &PathBuf) -> Result<Box<dyn BufRead>>
+{
+    let file = File::open(file_path)?;
+    #[cfg(feature = "progress")]
+    let file = progress::tracked(file, file_path)?;
+    let mut reader = BufReader::new(file);
+    match reader.fill_buf()?.starts_with(&GZIP_MAGIC)
+    {
+        true => Ok(Box::new(RadixReader::new(ScopeBalancer::new(BufReader::new(GzDecoder::new(reader)), file_path)))),
+        false => Ok(Box::new(RadixReader::new(ScopeBalancer::new(reader, file_path)))),
+    }
+}
+
+// How VCD::merge_rounded moves two traces
+struct MergeShifts
+{
+    self_shift : u64,
+    vcd_shift : u64,
+    /// Sync point of the merged trace
+    sync : SyncPoint,
+    /// Timeskew before rounding
+    raw_timeskew : u64,
+}
+
+// `self_end` is the last timestamp of the trace merged into
+fn merge_shifts(self_sync : SyncPoint, self_end : Option<u64>, vcd_sync : SyncPoint, period : u64) -> Result<MergeShifts>
+{
+    let round = |shift : u64| shift.div_ceil(period.max(1)).saturating_mul(period.max(1));
+    // Both traces are moved forward so that the later of the two sync
+    // points is kept, which never needs a negative timestamp so no
+    // shift can underflow
+    let (self_shift, vcd_shift, sync) = match (self_sync, vcd_sync)
+    {
+        (SyncPoint::ResetSignal(a), SyncPoint::ResetSignal(b)) =>
+        {
+            let end = a.max(b);
+            (end - a, end - b, SyncPoint::ResetSignal(end))
+        },
+        (SyncPoint::ManualOffset(a), SyncPoint::ManualOffset(b)) =>
+        {
+            let base = a.min(b);
+            (a.abs_diff(base), b.abs_diff(base), SyncPoint::ManualOffset(base))
+        },
+        (SyncPoint::Append, SyncPoint::Append) =>
+        {
+            let end = match self_end
+            {
+                Some(timestamp) => timestamp.checked_add(1).ok_or(VcdSyncError::ShiftOverflow { timestamp, timeskew : 1 })?,
+                None => 0,
+            };
+            (0, end, SyncPoint::Append)
+        },
+        (first, second) => return Err(VcdSyncError::SyncMismatch { first, second }),
+    };
+    let raw_timeskew = self_shift.max(vcd_shift);
+    match sync
+    {
+        SyncPoint::ResetSignal(_) => Ok(MergeShifts { self_shift : round(self_shift), vcd_shift : round(vcd_shift), sync, raw_timeskew }),
+        _ => Ok(MergeShifts { self_shift, vcd_shift, sync, raw_timeskew }),
+    }
+}
+
+/// Full paths of the signals of several traces, by how many traces have them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SignalSets
+{
+    /// Signals every trace has, sorted
+    pub common : Vec<String>,
+    /// Per trace, sorted signals no other trace has
+    pub unique : Vec<Vec<String>>,
+}
+
+impl SignalSets
+{
+    pub fn new(vcds : &[VCD]) -> SignalSets
+    {
+        let names : Vec<HashSet<String>> = vcds.iter().map(VCD::full_names).collect();
+        let mut common : Vec<String> = match names.split_first()
+        {
+            Some((first, others)) => first.iter().filter(|name| others.iter().all(|other| other.contains(*name))).cloned().collect(),
+            None => Vec::new(),
+        };
+        common.sort();
+        let unique = names.iter().enumerate().map(|(i, own)|
+        {
+            let mut unique : Vec<String> = own.iter()
+                .filter(|name| names.iter().enumerate().all(|(j, other)| i == j || !other.contains(*name)))
+                .cloned()
+                .collect();
+            unique.sort();
+            unique
+        }).collect();
+        SignalSets { common, unique }
+    }
+}
+
+/// Parses every file on its own thread, `parse` gets the index of the file
+/// in `files` and its path. Traces are returned in the order of `files`.
+pub fn parse_all<F>(files : &[PathBuf], parse : F) -> Result<Vec<VCD>>
+where
+    F : Fn(usize, &PathBuf) -> Result<VCD> + Sync,
+{
+    let parse = &parse;
+    std::thread::scope(|scope|
+    {
+        let parsers : Vec<_> = files.iter().enumerate().map(|(i, file)| scope.spawn(move || parse(i, file))).collect();
+        parsers.into_iter()
+            .map(|parser| parser.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    })
+}
+
+/// Merges `files` synced on `reset_signal`, see [`merge_all_with`]
+pub fn merge_all(files : &[PathBuf], reset_signal : &str) -> Result<VCD>
+{
+    merge_all_with(files, reset_signal, &ParseOptions::default())
+}
+
+/// Parses `files` in parallel, scales them to a common timescale and merges
+/// them in order into the first one. Every trace is aligned on the latest
+/// reset release of all of them, whichever file it comes from.
+pub fn merge_all_with(files : &[PathBuf], reset_signal : &str, options : &ParseOptions) -> Result<VCD>
+{
+    let mut vcds = parse_all(files, |_, file| VCD::new(file, reset_signal, options))?;
+    let normalizer = TimescaleNormalizer::new(&vcds)?;
+    for vcd in vcds.iter_mut()
+    {
+        normalizer.normalize(vcd)?;
+    }
+    let mut vcds = vcds.into_iter();
+    let mut merged = vcds.next().ok_or(VcdSyncError::NoInput)?;
+    for vcd in vcds
+    {
+        merged.merge(vcd)?;
+    }
+    Ok(merged)
+}
+
+/// Shift every trace gets when all of them are merged with [`VCD::merge`].
+///
+/// Traces must already share the same timescale.
+pub fn timeskews(vcds : &[VCD]) -> Vec<u64>
+{
+    rounded_timeskews(vcds, 1)
+}
+
+/// Shift every trace gets when all of them are merged in order with
+/// [`VCD::merge_rounded`], traces that can't be merged get no shift.
+pub fn rounded_timeskews(vcds : &[VCD], period : u64) -> Vec<u64>
+{
+    // Replays the merges on the sync points only
+    let mut timeskews : Vec<u64> = Vec::new();
+    let mut merged : Option<(SyncPoint, Option<u64>)> = None;
+    for vcd in vcds
+    {
+        let vcd_end = vcd.values.last_key_value().map(|(timestamp, _)| *timestamp);
+        let Some((sync, end)) = merged else
+        {
+            merged = Some((vcd.sync, vcd_end));
+            timeskews.push(0);
+            continue;
+        };
+        match merge_shifts(sync, end, vcd.sync, period)
+        {
+            Ok(shifts) =>
+            {
+                timeskews.iter_mut().for_each(|timeskew| *timeskew = timeskew.saturating_add(shifts.self_shift));
+                timeskews.push(shifts.vcd_shift);
+                let end = end.map(|end| end.saturating_add(shifts.self_shift))
+                    .max(vcd_end.map(|end| end.saturating_add(shifts.vcd_shift)));
+                merged = Some((shifts.sync, end));
+            },
+            Err(_) => timeskews.push(0),
+        }
+    }
+    timeskews
+}
+
+/// Brings several traces to a common timescale so they can be merged.
+///
+/// The common timescale is the largest tick every input tick is a whole
+/// multiple of, which is simply the finer of the two for `1 ns` / `100 ps`.
+pub struct TimescaleNormalizer
+{
+    pub timescale_value: u32,
+    pub timescale_unit : TimescaleUnit,
+}
+
+const TIMESCALE_UNITS : [TimescaleUnit; 6] = [TimescaleUnit::S, TimescaleUnit::MS, TimescaleUnit::US,
+                                              TimescaleUnit::NS, TimescaleUnit::PS, TimescaleUnit::FS];
+
+/// Length of a `unit` in femtoseconds, e.g. 1000 for ps, so that units can
+/// be compared
+pub fn unit_to_femtoseconds(unit : TimescaleUnit) -> u64
+{
+    TimescaleUnit::FS.divisor() / unit.divisor()
+}
+
+// Length of a timescale tick in femtoseconds
+fn tick_fs(value : u32, unit : TimescaleUnit) -> u128
+{
+    value as u128 * unit_to_femtoseconds(unit) as u128
+}
+
+/// Scales the coarser of two traces to the timescale of the finer one (or
+/// both traces to a common one, e.g. for `10 ns` and `4 ns`), traces that
+/// already share their timescale are left untouched
+pub fn normalize_timescale(vcd1 : &mut VCD, vcd2 : &mut VCD) -> Result<()>
+{
+    let normalizer = TimescaleNormalizer::for_timescales([(vcd1.timescale_value, vcd1.timescale_unit),
+                                                          (vcd2.timescale_value, vcd2.timescale_unit)])?;
+    normalizer.normalize(vcd1)?;
+    normalizer.normalize(vcd2)
+}
+
+/// `timestamp` ticks of `value` `unit` in the coarsest unit giving an
+/// integer, e.g. `42 us` for 42000000 ticks of 1 ps
+pub fn display_time(timestamp : u64, value : u32, unit : TimescaleUnit) -> String
+{
+    let fs = timestamp as u128 * tick_fs(value, unit);
+    // Femtoseconds always divide it, only 0 keeps the trace unit
+    match TIMESCALE_UNITS.iter().find(|coarser| fs > 0 && fs.is_multiple_of(tick_fs(1, **coarser)))
+    {
+        Some(coarser) => format!("{} {}", fs / tick_fs(1, *coarser), coarser),
+        None => format!("0 {}", unit),
+    }
+}
+
+fn gcd(a : u128, b : u128) -> u128
+{
+    match b
+    {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+impl TimescaleNormalizer
+{
+    pub fn new(vcds : &[VCD]) -> Result<TimescaleNormalizer>
+    {
+        TimescaleNormalizer::for_timescales(vcds.iter().map(|vcd| (vcd.timescale_value, vcd.timescale_unit)))
+    }
+
+    fn for_timescales(timescales : impl IntoIterator<Item = (u32, TimescaleUnit)>) -> Result<TimescaleNormalizer>
+    {
+        let mut tick = None;
+        for (value, unit) in timescales
+        {
+            if value == 0
+            {
+                return Err(VcdSyncError::InvalidTimescale { value, unit, reason : "is empty" });
+            }
+            let unit_tick = tick_fs(value, unit);
+            tick = Some(tick.map_or(unit_tick, |tick| gcd(tick, unit_tick)));
+        }
+        let tick = tick.ok_or(VcdSyncError::NoInput)?;
+
+        // Express the tick with the coarsest unit giving an integer value
+        for unit in TIMESCALE_UNITS
+        {
+            let unit_fs = tick_fs(1, unit);
+            if tick % unit_fs == 0
+            {
+                if let Ok(timescale_value) = u32::try_from(tick / unit_fs)
+                {
+                    return Ok(TimescaleNormalizer{ timescale_value, timescale_unit : unit });
+                }
+            }
+        }
+        unreachable!("a femtosecond tick always fits")
+    }
+
+    /// Normalizer to a given timescale instead of the finest one of the
+    /// inputs, coarser traces are scaled up and finer ones truncated
+    pub fn forced(timescale_value : u32, timescale_unit : TimescaleUnit) -> Result<TimescaleNormalizer>
+    {
+        match timescale_value
+        {
+            0 => Err(VcdSyncError::InvalidTimescale { value : timescale_value, unit : timescale_unit, reason : "is empty" }),
+            _ => Ok(TimescaleNormalizer { timescale_value, timescale_unit }),
+        }
+    }
+
+    /// Scales every timestamp of `vcd` to the common timescale. Timestamps
+    /// falling between two ticks of a forced coarser timescale are
+    /// truncated, with a warning
+    pub fn normalize(&self, vcd : &mut VCD) -> Result<()>
+    {
+        if vcd.timescale_value == self.timescale_value && vcd.timescale_unit == self.timescale_unit
+        {
+            return Ok(());
+        }
+
+        let (from, to) = (tick_fs(vcd.timescale_value, vcd.timescale_unit), tick_fs(self.timescale_value, self.timescale_unit));
+        let divisor = gcd(from, to);
+        let (factor, divisor) = (from / divisor, to / divisor);
+        warning!("Rescaling trace from {} {} to {} {} ({})",
+                 vcd.timescale_value, vcd.timescale_unit,
+                 self.timescale_value, self.timescale_unit,
+                 match divisor
+                 {
+                     1 => format!("x{}", factor),
+                     _ if factor == 1 => format!("/{}", divisor),
+                     _ => format!("x{}/{}", factor, divisor),
+                 });
+        let scale = |timestamp : u64| u64::try_from(timestamp as u128 * factor / divisor)
+            .map_err(|_| VcdSyncError::TimestampOverflow {
+                timestamp,
+                from : (vcd.timescale_value, vcd.timescale_unit),
+                to : (self.timescale_value, self.timescale_unit),
+            });
+
+        let mut values = TimestampValues::new();
+        let mut truncated = 0;
+        for (timestamp, changes) in std::mem::take(&mut vcd.values)
+        {
+            if !(timestamp as u128 * factor).is_multiple_of(divisor)
+            {
+                truncated += 1;
+            }
+            // Changes truncated to the same tick are kept in order, the
+            // last one wins
+            values.entry(scale(timestamp)?).or_default().extend(changes);
+        }
+        if truncated > 0
+        {
+            warning!("{} timestamps between two ticks of {} {}, truncated",
+                     truncated, self.timescale_value, self.timescale_unit);
+        }
+        // Manual offsets are already given in the merged timescale
+        if let SyncPoint::ResetSignal(rst_end) = vcd.sync
+        {
+            vcd.sync = SyncPoint::ResetSignal(scale(rst_end)?);
+        }
+        for source in vcd.sources.iter_mut()
+        {
+            if let SyncPoint::ResetSignal(rst_end) = source.sync
+            {
+                source.sync = SyncPoint::ResetSignal(scale(rst_end)?);
+            }
+            for section in source.dump_sections.iter_mut()
+            {
+                section.timestamp = scale(section.timestamp)?;
+            }
+        }
+        vcd.values = values;
+        vcd.timescale_value = self.timescale_value;
+        vcd.timescale_unit = self.timescale_unit;
+        Ok(())
+    }
+}
+
+/// Flattens the header scope tree into the list of declared variables
+pub fn signals(items: &[ScopeItem]) -> SignalsCode {
+    let mut results = Vec::new();
+
+    fn recursive_collect(
+        items: &[ScopeItem],
+        current_scope: &mut Vec<String>,
+        results: &mut SignalsCode,
+    ) {
+        for item in items {
+            match item {
+                ScopeItem::Var(var) => {
+                    let signal = Signal {
+                        scope : current_scope.clone(),
+                        name : var.reference.clone(),
+                        width : var.size,
+                        var_type : var.var_type,
+                        source : 0,
+                        index : var.index,
+                        code : Some(var.code),
+                    };
+                    results.push((signal, var.code));
+                }
+                ScopeItem::Scope(scope) => {
+                    current_scope.push(scope.identifier.clone());
+                    recursive_collect(&scope.items, current_scope, results);
+                    current_scope.pop();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    recursive_collect(items, &mut Vec::new(), &mut results);
+    results
+}
+
+/// Every variable declared in the header of a trace, unfiltered, without
+/// reading its value changes
+pub fn declared_signals(file_path : &PathBuf) -> Result<Vec<Signal>>
+{
+    let header = Parser::new(open_trace(file_path)?).parse_header()?;
+    Ok(signals(&header.items).into_iter().map(|(signal, _)| signal).collect())
+}
+
+/// Worst-case memory needed to parse and merge these files in memory, 8
+/// bytes per byte of input
+pub fn estimated_memory(file_paths : &[PathBuf]) -> Result<u64>
+{
+    let mut total = 0u64;
+    for file_path in file_paths
+    {
+        total = total.saturating_add(std::fs::metadata(file_path)?.len().saturating_mul(8));
+    }
+    Ok(total)
+}
+
+/// Full name and IdCode of every variable called `leaf_name`, whatever its
+/// scope, in declaration order
+pub fn find_signal_by_leaf(items : &[ScopeItem], leaf_name : &str) -> Vec<(String, IdCode)>
+{
+    signals(items).into_iter()
+        .filter(|(signal, _)| signal.name == leaf_name)
+        .map(|(signal, code)| (signal.full_name(), code))
+        .collect()
+}
+
+// Numeric value of an IdCode, which the vcd crate doesn't expose: its
+// derived Hash writes exactly that u64
+#[derive(Default)]
+struct IdCodeValue(u64);
+
+impl Hasher for IdCodeValue
+{
+    fn finish(&self) -> u64
+    {
+        self.0
+    }
+
+    fn write(&mut self, bytes : &[u8])
+    {
+        self.0 = bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64);
+    }
+
+    fn write_u64(&mut self, value : u64)
+    {
+        self.0 = value;
+    }
+}
+
+fn id_code_value(id : IdCode) -> u64
+{
+    let mut value = IdCodeValue::default();
+    id.hash(&mut value);
+    value.finish()
+}
+
+// Above this, IdCodes are too sparse to be indexed directly
+const DENSE_ID_LIMIT : u64 = 1 << 20;
+
+/// Signal index of every IdCode of a trace.
+///
+/// IdCodes are small integers in practice, they index a `Vec` directly;
+/// traces using very long codes fall back to a `HashMap`.
+pub struct IdMap(IdMapKind);
+
+enum IdMapKind
+{
+    Dense(Vec<Option<u32>>),
+    Sparse(HashMap<IdCode, u32>),
+}
+
+impl IdMap
+{
+    pub fn new(signals : &SignalsCode) -> IdMap
+    {
+        let max_id = signals.iter().map(|(_, id_code)| id_code_value(*id_code)).max().unwrap_or(0);
+        if max_id >= DENSE_ID_LIMIT
+        {
+            let ids = signals.iter().enumerate().map(|(i, (_, id_code))| (*id_code, i as u32)).collect();
+            return IdMap(IdMapKind::Sparse(ids));
+        }
+        let mut ids = vec![None; max_id as usize + 1];
+        for (i, (_, id_code)) in signals.iter().enumerate()
+        {
+            ids[id_code_value(*id_code) as usize] = Some(i as u32);
+        }
+        IdMap(IdMapKind::Dense(ids))
+    }
+
+    /// Index in `signals` of the signal with this IdCode, `None` if it was
+    /// filtered out or isn't declared
+    pub fn get(&self, id : IdCode) -> Option<u32>
+    {
+        match &self.0
+        {
+            IdMapKind::Dense(ids) => ids.get(id_code_value(id) as usize).copied().flatten(),
+            IdMapKind::Sparse(ids) => ids.get(&id).copied(),
+        }
+    }
+}
+
+/// What [`collect_values`] read of a trace
+#[derive(Clone, Debug)]
+pub struct CollectedValues
+{
+    pub values : TimestampValues,
+    pub reset_edge : ResetEdge,
+    pub dump_sections : Vec<DumpSection>,
+    /// Value changes in the file, of the signals left out of `values` too
+    pub changes : u64,
+}
+
+/// Reads every value change of the trace, with the time the reset was released
+/// and the `$dumpoff` / `$dumpon` sections
+///
+/// `file_path` is only used to report errors.
+pub fn collect_values<T>(file_path : &Path,
+                         signals: &SignalsCode,
+                         vcd: &mut Parser<T>,
+                         id_code : Option<IdCode>,
+                         options : &ParseOptions) -> Result<CollectedValues>
+where
+    T: std::io::BufRead,
+{
+    let mut values: TimestampValues = TimestampValues::new();
+    let mut current_timestamp = 0;
+    let mut timestamp_seen = false;
+    // First repeated timestamp, and how many are
+    let mut repeated : Option<(u64, u64)> = None;
+    let mut reset = ResetTracker::new(options.rst_polarity);
+    // $dumpall / $dumpoff sections repeat the current state or blank it,
+    // they are kept as regular changes but can't move the reset edge
+    let mut section = None;
+    let mut dump_sections : Vec<DumpSection> = Vec::new();
+    // Past the end of a dump section, until the next timestamp
+    let mut after_section = false;
+    let mut changes = 0;
+
+    let id_map = IdMap::new(signals);
+
+    for cmd in vcd.into_iter().flatten()
+    {
+        if let ChangeScalar(..) | ChangeVector(..) | ChangeReal(..) | ChangeString(..) = cmd
+        {
+            changes += 1;
+        }
+        // The last change of a variable at a timestamp is the one kept, a
+        // change after the section takes it out of the section
+        if let (ChangeScalar(id, _) | ChangeVector(id, _) | ChangeReal(id, _) | ChangeString(id, _), Some(dump_section)) = (&cmd, dump_sections.last_mut())
+        {
+            match section
+            {
+                Some(SimulationCommand::Dumpoff | SimulationCommand::Dumpon) => dump_section.codes.push(*id),
+                _ if after_section => dump_section.codes.retain(|code| code != id),
+                _ => (),
+            }
+        }
+        match cmd
+        {
+            ChangeScalar(id, value) =>
+            {
+                // Signals filtered out of the trace have no entry
+                if let Some(index) = id_map.get(id)
+                {
+                    values.entry(current_timestamp)
+                        .or_default()
+                        .push((index, StoredValue::Scalar(value)));
+                }
+                if Some(id) == id_code && !matches!(section, Some(SimulationCommand::Dumpall | SimulationCommand::Dumpoff))
+                {
+                    reset.update(current_timestamp, value);
+                }
+            },
+            ChangeVector(id, value) =>
+            {
+                if let Some(index) = id_map.get(id)
+                {
+                    values.entry(current_timestamp)
+                        .or_default()
+                        .push((index, StoredValue::Vector(value)));
+                }
+            },
+            ChangeReal(id, value) =>
+            {
+                if let Some(index) = id_map.get(id)
+                {
+                    values.entry(current_timestamp)
+                        .or_default()
+                        .push((index, StoredValue::Real(value)));
+                }
+            },
+            ChangeString(id, value) =>
+            {
+                if let Some(index) = id_map.get(id)
+                {
+                    values.entry(current_timestamp)
+                        .or_default()
+                        .push((index, StoredValue::Str(value)));
+                }
+            },
+            Begin(command) =>
+            {
+                match command
+                {
+                    SimulationCommand::Dumpoff =>
+                        dump_sections.push(DumpSection { timestamp : current_timestamp, command : DumpCommand::Off, codes : Vec::new() }),
+                    SimulationCommand::Dumpon =>
+                        dump_sections.push(DumpSection { timestamp : current_timestamp, command : DumpCommand::On, codes : Vec::new() }),
+                    _ => (),
+                }
+                section = Some(command);
+            },
+            End(_) =>
+            {
+                after_section = matches!(section, Some(SimulationCommand::Dumpoff | SimulationCommand::Dumpon));
+                section = None;
+            },
+            Timestamp(timestamp) =>
+            {
+              if timestamp < current_timestamp
+              {
+                  if options.strict
+                  {
+                      return Err(VcdSyncError::NonMonotonicTimestamp {
+                          file : file_path.to_path_buf(),
+                          prev : current_timestamp,
+                          next : timestamp,
+                      });
+                  }
+                  warning!("Non-monotonic timestamp in {}: #{} after #{}",
+                           file_path.display(), timestamp, current_timestamp);
+              }
+              // A malformed trace may repeat a timestamp right after itself
+              if timestamp_seen && timestamp == current_timestamp
+              {
+                  repeated.get_or_insert((timestamp, 0)).1 += 1;
+              }
+              timestamp_seen = true;
+              current_timestamp = timestamp;
+              after_section = false;
+            },
+            // XXX collect other value type ?
+            _ => (),
+        }
+    }
+    if let Some((first, count)) = repeated
+    {
+        warning!("{} repeated timestamps in {}, e.g. #{}, their changes are kept in a single timestamp",
+                 count, file_path.display(), first);
+    }
+
+    Ok(CollectedValues { values, reset_edge : reset.edge, dump_sections, changes })
+}
+
+// A scope of the output file, items are kept in first-seen order
+#[derive(Default)]
+pub(crate) struct ScopeNode
+{
+    pub(crate) wires : Vec<u32>,
+    pub(crate) scopes : Vec<(String, ScopeNode)>,
+}
+
+impl ScopeNode
+{
+    pub(crate) fn build(signals : &[Signal], options : &WriteOptions) -> ScopeNode
+    {
+        let mut root = ScopeNode::default();
+        for (id, signal) in signals.iter().enumerate()
+        {
+            let mut node = &mut root;
+            for name in &signal.scope
+            {
+                let pos = match node.scopes.iter().position(|(n, _)| n == name)
+                {
+                    Some(pos) => pos,
+                    None => { node.scopes.push((name.clone(), ScopeNode::default())); node.scopes.len() - 1 }
+                };
+                node = &mut node.scopes[pos].1;
+            }
+            node.wires.push(id as u32);
+        }
+
+        // Signals declared outside of any scope still need a module to live in
+        let module_name = options.module_name.as_deref().unwrap_or("top");
+        match options.wrap_in_top
+        {
+            true => ScopeNode { wires : Vec::new(), scopes : vec![(module_name.to_string(), root)] },
+            false if !root.wires.is_empty() =>
+            {
+                let wires = std::mem::take(&mut root.wires);
+                root.scopes.insert(0, (module_name.to_string(), ScopeNode { wires, scopes : Vec::new() }));
+                root
+            },
+            false => root,
+        }
+    }
+
+    fn write<W: std::io::Write>(&self,
+                                writer : &mut vcd::Writer<W>,
+                                signals : &[Signal],
+                                preserved : Option<&HashMap<u32, IdCode>>,
+                                signals_map : &mut HashMap<u32, IdCode>) -> Result<()>
+    {
+        for id in &self.wires
+        {
+            let signal = &signals[*id as usize];
+            let code = match preserved
+            {
+                Some(codes) =>
+                {
+                    let code = codes[id];
+                    writer.var_def(signal.var_type, signal.width, code, &signal.name, signal.index)?;
+                    code
+                },
+                None => writer.add_var(signal.var_type, signal.width, &signal.name, signal.index)?,
+            };
+            signals_map.insert(*id, code);
+        }
+        // Each module is closed right after its content, so the header stays
+        // balanced at any depth
+        for (name, scope) in &self.scopes
+        {
+            writer.add_module(name)?;
+            scope.write(writer, signals, preserved, signals_map)?;
+            writer.upscope()?;
+        }
+        Ok(())
+    }
+}
+
+// Lines of the provenance comment, timeskews are the ones of the merge
+fn provenance(merged : &VCD) -> Vec<String>
+{
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut lines = vec![format!("Merged by vcd_sync {} on {} UTC", env!("CARGO_PKG_VERSION"), utc_datetime(now))];
+    for source in &merged.sources
+    {
+        lines.push(format!("{} : {}, timeskew {}",
+                           source.path.display(),
+                           source.sync,
+                           display_time(source.timeskew, merged.timescale_value, merged.timescale_unit)));
+    }
+    lines
+}
+
+// `YYYY-MM-DD hh:mm:ss` of a unix time
+fn utc_datetime(secs : u64) -> String
+{
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year, month, day,
+            secs % 86400 / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Options controlling how a (merged) trace is written
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions
+{
+    /// First timestamp written, the state of every signal at that time is
+    /// written there so the window starts from a known state
+    pub trim_start : Option<u64>,
+    /// Last timestamp written
+    pub trim_end : Option<u64>,
+    /// How signals sharing the same full path are renamed
+    pub name_conflict : NameConflict,
+    /// Record the inputs, their sync points and the tool version in a
+    /// `$comment` of the header
+    pub provenance : bool,
+    /// Full paths (after renaming) of signals written with their 0 and 1
+    /// bits swapped, x and z are kept
+    pub invert : Vec<String>,
+    /// `(old, new)` scope renames, applied in order to the first scope named
+    /// `old` of every signal path, after the prefixes are added
+    pub scope_renames : Vec<(String, String)>,
+    /// `(old, new)` signal renames, applied once duplicate names are
+    /// resolved: `old` is a full path of the output, `new` a leaf name or,
+    /// with dots, a full path
+    pub signal_renames : Vec<(String, String)>,
+    /// Module holding the signals declared in no scope, `top` by default
+    pub module_name : Option<String>,
+    /// Nest every root scope of the output in that module too
+    pub wrap_in_top : bool,
+    /// Don't write the `$date` and `$version` of the inputs
+    pub strip_header_metadata : bool,
+    /// Write every signal with its input IdCode, unless an earlier signal
+    /// already took it, instead of numbering them from `!`
+    pub preserve_id_codes : bool,
+    /// Follow every timestamp with a `$comment T=42 ns $end` of its time,
+    /// for text searches
+    pub timestamp_comments : bool,
+    /// Write the signals sorted by full path, see [`VCD::sort_signals`]
+    pub sort_signals : bool,
+}
+
+// Naming and display transforms of `options`, shared by every output format
+pub(crate) fn prepare_output(merged : &mut VCD, options : &WriteOptions)
+{
+    prepare_names(merged, options);
+    if options.sort_signals
+    {
+        merged.sort_signals();
+    }
+    // Some tools reject a signal changing twice at the same timestamp
+    let duplicates = dedup_values(&mut merged.values);
+    if duplicates > 0
+    {
+        warning!("Dropped {} value changes overridden at the same timestamp", duplicates);
+    }
+    prune_empty_timestamps(&mut merged.values);
+    let ids = inverted_ids(merged, options);
+    for (_, value) in merged.values.values_mut().flatten().filter(|(id, _)| ids.contains(id))
+    {
+        *value = inverted(value);
+    }
+}
+
+// Signals of `options.invert`, once renamed
+fn inverted_ids(merged : &VCD, options : &WriteOptions) -> HashSet<u32>
+{
+    let mut ids = HashSet::new();
+    for name in &options.invert
+    {
+        match merged.signal_id(name)
+        {
+            Some(id) => _ = ids.insert(id),
+            None => warning!("Signal {} to invert not found", name),
+        }
+    }
+    ids
+}
+
+// Renames of `options`, the only transforms not touching the values
+fn prepare_names(merged : &mut VCD, options : &WriteOptions)
+{
+    let module_name = options.module_name.as_deref().unwrap_or("top");
+    for old in merged.resolve_renamed_conflicts(options.name_conflict, &options.scope_renames, module_name)
+    {
+        warning!("Scope {} to rename not found", old);
+    }
+    for (old, new) in &options.signal_renames
+    {
+        let Some(id) = merged.signal_id(old) else
+        {
+            warning!("Signal {} to rename not found", old);
+            continue;
+        };
+        let signal = &mut merged.signals[id as usize];
+        match new.rsplit_once('.')
+        {
+            Some((scope, name)) =>
+            {
+                signal.scope = scope.split('.').map(str::to_string).collect();
+                signal.name = name.to_string();
+            },
+            None => signal.name = new.clone(),
+        }
+        let (path, index) = (signal.full_name(), signal.index);
+        if merged.signals.iter().enumerate().any(|(other, signal)| other != id as usize && signal.full_name() == path && signal.index == index)
+        {
+            warning!("Signal {} renamed to {}, which is already taken", old, path);
+        }
+    }
+}
+
+// New id of every signal once sorted by full path and index
+fn sorted_ids(signals : &[Signal]) -> Vec<u32>
+{
+    // Bounds of the index, a bit select being a range of one bit
+    let bounds = |index : Option<ReferenceIndex>| index.map(|index| match index
+    {
+        ReferenceIndex::BitSelect(bit) => (bit, bit),
+        ReferenceIndex::Range(msb, lsb) => (msb, lsb),
+    });
+    let mut order : Vec<_> = signals.iter().enumerate()
+        .map(|(id, signal)| (signal.full_name(), bounds(signal.index), id as u32))
+        .collect();
+    order.sort();
+    let mut new_ids = vec![0; signals.len()];
+    for (new_id, (_, _, id)) in order.into_iter().enumerate()
+    {
+        new_ids[id as usize] = new_id as u32;
+    }
+    new_ids
+}
+
+// Stable, a signal changing twice keeps its changes in order
+fn renumber_changes(changes : &mut [(u32, StoredValue)], new_ids : &[u32])
+{
+    for (id, _) in changes.iter_mut()
+    {
+        *id = new_ids[*id as usize];
+    }
+    changes.sort_by_key(|(id, _)| *id);
+}
+
+/// Only keeps the last change of a signal at each timestamp, returns the
+/// number of changes removed
+pub fn dedup_values(values : &mut TimestampValues) -> usize
+{
+    let mut seen = HashSet::new();
+    values.values_mut().map(|changes| dedup_changes(changes, &mut seen)).sum()
+}
+
+/// Removes the timestamps left without any change, e.g. once their signals
+/// are filtered out, they would be written as a lone `#N`
+pub fn prune_empty_timestamps(values : &mut TimestampValues)
+{
+    values.retain(|_, changes| !changes.is_empty());
+}
+
+/// Moves the changes following a timestamp by at most `window` ticks to
+/// that timestamp, e.g. changes a few ticks apart after a merge of
+/// unrounded timeskews. Returns how many timestamps were merged away,
+/// changes are kept in order so that the last one of a signal wins.
+pub fn merge_zero_duration_timestamps(values : &mut TimestampValues, window : u64) -> usize
+{
+    let len = values.len();
+    let mut merged = TimestampValues::new();
+    let mut group_start = None;
+    for (timestamp, changes) in std::mem::take(values)
+    {
+        let start = match group_start
+        {
+            Some(start) if timestamp - start <= window => start,
+            _ => timestamp,
+        };
+        group_start = Some(start);
+        merged.entry(start).or_default().extend(changes);
+    }
+    *values = merged;
+    len - values.len()
+}
+
+// dedup_values of a single timestamp, `seen` is only reused for its allocation
+fn dedup_changes(changes : &mut Vec<(u32, StoredValue)>, seen : &mut HashSet<u32>) -> usize
+{
+    let len = changes.len();
+    // Kept from the end so that the last change of each signal wins
+    seen.clear();
+    changes.reverse();
+    changes.retain(|(id, _)| seen.insert(*id));
+    changes.reverse();
+    len - changes.len()
+}
+
+fn inverted(value : &StoredValue) -> StoredValue
+{
+    let invert = |value : Value| match value
+    {
+        Value::V0 => Value::V1,
+        Value::V1 => Value::V0,
+        value => value,
+    };
+    match value
+    {
+        StoredValue::Scalar(value) => StoredValue::Scalar(invert(*value)),
+        StoredValue::Vector(vector) => StoredValue::Vector(vector.iter().map(invert).collect()),
+        StoredValue::Real(value) => StoredValue::Real(*value),
+        StoredValue::Str(value) => StoredValue::Str(value.clone()),
+    }
+}
+
+// Restricts the values to the trim window of `options`
+pub(crate) fn trim_values(values : TimestampValues, options : &WriteOptions) -> TimestampValues
+{
+    let start = options.trim_start.unwrap_or(0);
+    let end = options.trim_end.unwrap_or(u64::MAX);
+    if start == 0 && end == u64::MAX
+    {
+        return values;
+    }
+    window_values(values, start, end)
+}
+
+// trim_values of merged timestamps as they come, the ones up to the trim
+// start are folded into its snapshot
+fn trim_stream<'a, I>(values : I, options : &WriteOptions) -> Box<dyn Iterator<Item = TimestampChanges> + 'a>
+where
+    I : Iterator<Item = TimestampChanges> + 'a,
+{
+    let start = options.trim_start.unwrap_or(0);
+    let end = options.trim_end.unwrap_or(u64::MAX);
+    if start == 0 && end == u64::MAX
+    {
+        return Box::new(values);
+    }
+    if start > end
+    {
+        return Box::new(std::iter::empty());
+    }
+    let mut values = values.peekable();
+    let mut last = HashMap::new();
+    while let Some((_, changes)) = values.next_if(|(timestamp, _)| *timestamp <= start)
+    {
+        last.extend(changes);
+    }
+    let mut snapshot : Vec<(u32, StoredValue)> = last.into_iter().collect();
+    snapshot.sort_by_key(|(id, _)| *id);
+    let snapshot = (!snapshot.is_empty()).then_some((start, snapshot));
+    Box::new(snapshot.into_iter().chain(values.take_while(move |(timestamp, _)| *timestamp <= end)))
+}
+
+// Keeps the values in [start, end], the state of every signal at `start`
+// is written there
+fn window_values(values : TimestampValues, start : u64, end : u64) -> TimestampValues
+{
+    if start > end
+    {
+        return TimestampValues::new();
+    }
+    // Changes happening right at the start are part of the snapshot
+    let mut snapshot : Vec<(u32, StoredValue)> = last_values_at(&values, start).into_iter().collect();
+    snapshot.sort_by_key(|(id, _)| *id);
+
+    let mut trimmed : TimestampValues = values.into_iter()
+        .filter(|(timestamp, _)| *timestamp > start && *timestamp <= end)
+        .collect();
+    if !snapshot.is_empty()
+    {
+        trimmed.insert(start, snapshot);
+    }
+    trimmed
+}
+
+// See VCD::last_values_at
+fn last_values_at(values : &TimestampValues, timestamp : u64) -> HashMap<u32, StoredValue>
+{
+    let mut last = HashMap::new();
+    for changes in values.range(..=timestamp).map(|(_, changes)| changes)
+    {
+        last.extend(changes.iter().cloned());
+    }
+    last
+}
+
+/// Buffered output file, `-` is stdout
+pub fn create_output(output_file : &Path) -> Result<Box<dyn Write>>
+{
+    match output_file.as_os_str() == "-"
+    {
+        true => Ok(Box::new(BufWriter::new(io::stdout().lock()))),
+        false => Ok(Box::new(BufWriter::new(File::create(output_file)?))),
+    }
+}
+
+// One value change of the output
+fn write_change<W : Write>(writer : &mut vcd::Writer<W>, id_code : IdCode, value : StoredValue) -> Result<()>
+{
+    match value
+    {
+        StoredValue::Scalar(value) => writer.change_scalar(id_code, value)?,
+        StoredValue::Vector(value) => writer.change_vector(id_code, &value)?,
+        StoredValue::Real(value) => writer.change_real(id_code, value)?,
+        // VCD can't spell an empty text, it's what a string starts as anyway
+        StoredValue::Str(value) if value.is_empty() => (),
+        StoredValue::Str(value) => writer.change_string(id_code, &value)?,
+    }
+    Ok(())
+}
+
+/// Writes a (merged) trace to `output_file`, or to stdout if it's `-`
+pub fn write_vcd_to_file(merged : VCD, output_file : &Path, options : &WriteOptions) -> Result<()>
+{
+    write_vcd_to_writer(merged, create_output(output_file)?, options)
+}
+
+/// Writes a (merged) trace to any writer, e.g. a `Vec<u8>` or a socket. It
+/// is flushed but not buffered, wrap unbuffered writers in a `BufWriter`.
+pub fn write_vcd_to_writer<W : Write>(mut merged : VCD, output : W, options : &WriteOptions) -> Result<()>
+{
+    prepare_output(&mut merged, options);
+    let (mut writer, signals_map) = write_header(&merged, output, options)?;
+    let comment_timescale = options.timestamp_comments.then_some((merged.timescale_value, merged.timescale_unit));
+    write_body(&mut writer, &merged.sources, &merged.signals, &signals_map, trim_values(merged.values, options), comment_timescale)
+}
+
+// Header of a prepared trace, returns the IdCode of every signal
+fn write_header<W : Write>(merged : &VCD, output : W, options : &WriteOptions) -> Result<(vcd::Writer<W>, HashMap<u32, IdCode>)>
+{
+    let mut writer = vcd::Writer::new(output);
+    if !options.strip_header_metadata
+    {
+        if let Some(date) = &merged.date
+        {
+            writer.date(date)?;
+        }
+        if let Some(version) = &merged.version
+        {
+            writer.version(version)?;
+        }
+    }
+    writer.timescale(merged.timescale_value, merged.timescale_unit)?;
+    if options.provenance
+    {
+        writer.comment(&provenance(merged).join("\n    "))?;
+    }
+
+    let root = ScopeNode::build(&merged.signals, options);
+    let preserved = options.preserve_id_codes.then(|| preserved_id_codes(&merged.signals));
+    let mut signals_map : HashMap<u32, IdCode>  =  HashMap::new();
+    root.write(&mut writer, &merged.signals, preserved.as_ref(), &mut signals_map)?;
+
+    writer.enddefinitions()?;
+    Ok((writer, signals_map))
+}
+
+// IdCode of every signal keeping its input one: signals of the first file
+// come first and win a conflict, the others get the first free codes
+fn preserved_id_codes(signals : &[Signal]) -> HashMap<u32, IdCode>
+{
+    let mut taken = HashSet::new();
+    let mut codes = HashMap::new();
+    for (id, signal) in signals.iter().enumerate()
+    {
+        if let Some(code) = signal.code.filter(|code| taken.insert(*code))
+        {
+            codes.insert(id as u32, code);
+        }
+    }
+    let mut next = IdCode::FIRST;
+    for id in 0..signals.len() as u32
+    {
+        codes.entry(id).or_insert_with(||
+        {
+            while !taken.insert(next)
+            {
+                next = next.next();
+            }
+            next
+        });
+    }
+    codes
+}
+
+// Value of a signal not dumped yet, or in a $dumpoff section
+fn unknown_value(signal : &Signal) -> StoredValue
+{
+    match (signal.var_type, signal.width)
+    {
+        (VarType::Real, _) => StoredValue::Real(0.0),
+        (VarType::String, _) => StoredValue::Str(String::new()),
+        (_, 1) => StoredValue::Scalar(Value::X),
+        (_, width) => StoredValue::Vector(Vector::filled(Value::X, width as usize)),
+    }
+}
+
+// Value changes of a trace, in timestamp order, with the $dumpoff / $dumpon
+// sections of `sources` in between. With a `comment_timescale`, every
+// timestamp is followed by its time in that timescale
+fn write_body<W, I>(writer : &mut vcd::Writer<W>,
+                    sources : &[Source],
+                    signals : &[Signal],
+                    signals_map : &HashMap<u32, IdCode>,
+                    values : I,
+                    comment_timescale : Option<(u32, TimescaleUnit)>) -> Result<()>
+where
+    W : Write,
+    I : IntoIterator<Item = TimestampChanges>,
+{
+    let write_timestamp = |writer : &mut vcd::Writer<W>, timestamp : u64| -> io::Result<()>
+    {
+        writer.timestamp(timestamp)?;
+        match comment_timescale
+        {
+            Some((value, unit)) => writeln!(writer.writer(), "$comment T={} $end", display_time(timestamp, value, unit)),
+            None => Ok(()),
+        }
+    };
+    // Output ids of the signals dumped in each section, by timestamp. A
+    // section only holds the signals of its own file, the others keep dumping
+    let mut sections : BTreeMap<u64, Vec<(DumpCommand, Vec<u32>)>> = BTreeMap::new();
+    for (index, source) in sources.iter().enumerate()
+    {
+        for section in &source.dump_sections
+        {
+            let codes : HashSet<&IdCode> = section.codes.iter().collect();
+            let ids = signals.iter().enumerate()
+                .filter(|(_, signal)| signal.source == index && signal.code.as_ref().is_some_and(|code| codes.contains(code)))
+                .map(|(id, _)| id as u32)
+                .collect();
+            sections.entry(section.timestamp).or_default().push((section.command, ids));
+        }
+    }
+    // Only needed to dump the signals of a $dumpon section that don't
+    // change there
+    let mut state : Vec<Option<StoredValue>> = vec![None; if sections.is_empty() { 0 } else { signals.len() }];
+    let mut sections = sections.into_iter().peekable();
+    let mut values = values.into_iter().peekable();
+    let mut first = true;
+    while let Some(&(next, _)) = values.peek()
+    {
+        // Sections before the first timestamp written were trimmed away
+        while first && sections.next_if(|(timestamp, _)| *timestamp < next).is_some() {}
+        let (timestamp, mut changes) = match sections.peek()
+        {
+            Some((timestamp, _)) if *timestamp < next => (*timestamp, Vec::new()),
+            _ => values.next().unwrap_or_default(),
+        };
+        let here = sections.next_if(|(section_timestamp, _)| *section_timestamp == timestamp).map(|(_, here)| here).unwrap_or_default();
+        write_timestamp(writer, timestamp)?;
+
+        // Changes are deduplicated, the one of a section signal is its own.
+        // A $dumpoff section is always x, even where a merge initialization
+        // replaced its changes, a $dumpon one without a change dumps the
+        // last state outside $dumpoff
+        let positions : HashMap<u32, usize> = match here.is_empty()
+        {
+            true => HashMap::new(),
+            false => changes.iter().enumerate().map(|(position, (id, _))| (*id, position)).collect(),
+        };
+        let mut taken = vec![false; changes.len()];
+        let mut dumps = Vec::new();
+        for (command, ids) in here
+        {
+            let mut dumped = Vec::new();
+            for id in ids
+            {
+                let change = positions.get(&id).copied();
+                if let Some(position) = change
+                {
+                    taken[position] = true;
+                }
+                match (change, command)
+                {
+                    (_, DumpCommand::Off) => dumped.push((id, unknown_value(&signals[id as usize]))),
+                    (Some(position), DumpCommand::On) => dumped.push((id, changes[position].1.clone())),
+                    (None, DumpCommand::On) => dumped.extend(state[id as usize].clone().map(|value| (id, value))),
+                }
+            }
+            dumps.push((command, dumped));
+        }
+        let mut taken = taken.into_iter();
+        changes.retain(|_| !taken.next().unwrap_or(false));
+
+        // Stricter readers want the state of every signal dumped first, the
+        // ones that don't change there yet are unknown
+        let dumpvars = std::mem::take(&mut first);
+        if dumpvars
+        {
+            writer.begin(vcd::SimulationCommand::Dumpvars)?;
+            let mut initial : HashMap<u32, StoredValue> = changes.into_iter().collect();
+            changes = signals.iter().enumerate()
+                .map(|(id, signal)| (id as u32, initial.remove(&(id as u32)).unwrap_or_else(|| unknown_value(signal))))
+                .collect();
+        }
+        for (id, value) in changes
+        {
+            if let Some(state) = state.get_mut(id as usize)
+            {
+                *state = Some(value.clone());
+            }
+            write_change(writer, signals_map[&id], value)?;
+        }
+        if dumpvars
+        {
+            writer.end()?;
+        }
+        for (command, dumped) in dumps
+        {
+            writer.begin(match command
+            {
+                DumpCommand::Off => vcd::SimulationCommand::Dumpoff,
+                DumpCommand::On => vcd::SimulationCommand::Dumpon,
+            })?;
+            for (id, value) in dumped
+            {
+                // The state $dumpon restores is the one from before $dumpoff
+                if let Some(state) = state.get_mut(id as usize).filter(|_| command == DumpCommand::On)
+                {
+                    *state = Some(value.clone());
+                }
+                write_change(writer, signals_map[&id], value)?;
+            }
+            writer.end()?;
+        }
+    }
+
+    // Reported here, a BufWriter dropped on its own ignores flush errors
+    writer.flush()?;
+    Ok(())
+}
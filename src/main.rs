@@ -1,260 +1,1176 @@
-use std::fs::File;
-use std::path::PathBuf;
-use std::io::{BufReader, BufWriter};
-use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, Subcommand, ValueEnum};
 use anyhow::{Context, Result, bail};
-use vcd::Command::{ChangeScalar, Timestamp};
-use vcd::{Parser, Value, ScopeItem, IdCode, TimescaleUnit};
+use glob::Pattern;
+use vcd_sync::{VCD, InitStrategy, SignalSets, SyncPoint, TimescaleUnit, Value, create_output, declared_signals, display_time, estimated_memory, merge_zero_duration_timestamps, parse_all, NameConflict, ParseOptions, ResetPolarity, SignalFilter, TimescaleNormalizer, WriteOptions,
+               VcdSyncError, timeskews, rounded_timeskews, write_vcd_to_file, write_fst, write_csv, write_json};
+use vcd_sync::cache::cached_parse;
+use vcd_sync::diagnostic::{self, Level, Stream};
+use vcd_sync::diff::{diff, write_vcd_diff};
+use vcd_sync::stats::MergeStats;
+use vcd_sync::validate::{MergeCheck, Severity, validate};
+use vcd_sync::warning;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat
+{
+    Vcd,
+    /// Much smaller than VCD, but can't be used as an input to a later merge
+    Fst,
+    /// One timestamp,signal_name,value row per value change
+    Csv,
+    /// Array of {"ts", "sig", "val"} objects, one per value change
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+#[value(rename_all = "snake_case")]
+enum AlignMode
+{
+    /// Sync on the release of --reset-signal
+    #[default]
+    ResetSignal,
+    /// Sync on the first scalar change of every file, for captures without
+    /// a reset. Less reliable: any earlier change moves the sync point
+    FirstEvent,
+}
 
 /// A tool to merge and resynchronize VCD files based on a common reset signal.
 #[derive(ClapParser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the VCD files
     #[arg(num_args = 2..)]
     vcd_files: Vec<PathBuf>,
 
     /// Name of the reset signal to resethronize on
-    #[arg(short, long)]
-    reset_signal: String,
+    #[arg(short, long, required_unless_present_any = ["offset", "append", "align_mode"])]
+    reset_signal: Option<String>,
+
+    /// What the files are synced on, first_event is only for files without
+    /// a reset signal
+    #[arg(long, value_enum, conflicts_with_all = ["offset", "append"])]
+    align_mode: Option<AlignMode>,
+
+    /// Shift of each file after the first one relative to the first one, in
+    /// merged timescale units, instead of syncing on a reset signal
+    #[arg(long, conflicts_with = "reset_signal", allow_negative_numbers = true)]
+    offset: Vec<i64>,
+
+    /// Concatenate the files instead of syncing them: each file starts one
+    /// tick after the last event of the files before it. Use it for
+    /// sequential captures (e.g. boot then steady state), the default mode
+    /// overlays captures of the same run aligned on their reset
+    #[arg(long, conflicts_with_all = ["reset_signal", "offset"])]
+    append: bool,
+
+    /// Reset is active high, sync on its falling edge instead of its rising edge
+    #[arg(long)]
+    reset_active_high: bool,
+
+    /// Sync a trace on t=0 with a warning when its reset is never released,
+    /// instead of failing
+    #[arg(long, conflicts_with_all = ["offset", "append"])]
+    allow_no_reset: bool,
+
+    /// --reset-signal is only the name of the reset, looked for in every
+    /// scope, the first match is used
+    #[arg(long, requires = "reset_signal")]
+    reset_signal_search: bool,
+
+    /// Reset signal to use in files without --reset-signal, tried in order
+    /// (repeatable), e.g. the name given by another simulator
+    #[arg(long, requires = "reset_signal")]
+    reset_signal_fallback: Vec<String>,
+
+    /// Move the changes of the merged signals to the nearest rising edge of
+    /// --clock-signal, dropping sub-cycle jitter
+    #[arg(long, requires = "clock")]
+    clock_align: bool,
+
+    /// Value (0, 1, x or z) every signal is initialized to at timestamp 0 of
+    /// a merge, instead of 0
+    #[arg(long)]
+    initialize_to: Option<Value>,
+
+    /// Initialize the signals of a merge to x rather than 0, same as
+    /// --initialize-to x
+    #[arg(long, conflicts_with = "initialize_to")]
+    keep_x_state: bool,
+
+    /// Values the signals of a merge are initialized to at timestamp 0:
+    /// all_zero (the default), all_x, all_z or by_var_type, where registers
+    /// are x, tristates z and wires 0
+    #[arg(long, value_enum, conflicts_with_all = ["initialize_to", "keep_x_state"])]
+    init_strategy: Option<InitStrategy>,
 
-    /// Path to the output merged VCD file
+    /// Timescale unit of files without a $timescale, instead of failing
+    #[arg(long)]
+    default_timescale_unit: Option<TimescaleUnit>,
+
+    /// Timescale value of files without a $timescale
+    #[arg(long, default_value_t = 1, requires = "default_timescale_unit")]
+    default_timescale_value: u32,
+
+    /// Timescale unit of the merged trace, instead of the finest timescale
+    /// of the inputs. Finer inputs are truncated to it, with a warning
+    #[arg(long)]
+    force_timescale_unit: Option<TimescaleUnit>,
+
+    /// Timescale value of the merged trace
+    #[arg(long, default_value_t = 1, requires = "force_timescale_unit")]
+    force_timescale_value: u32,
+
+    /// Override the declared width of a signal, as SIGNAL=WIDTH where SIGNAL
+    /// is its full path, e.g. top.cpu.data=32 (repeatable)
+    #[arg(long, value_parser = parse_width)]
+    force_bit_width: Vec<(String, u32)>,
+
+    /// Fail when a signal isn't declared with this width, as SIGNAL=WIDTH
+    /// where SIGNAL is its full path, e.g. top.cpu.regfile=32, checked in
+    /// every file after --force-bit-width (repeatable)
+    #[arg(long, value_parser = parse_width)]
+    assert_signal_width: Vec<(String, u32)>,
+
+    /// Clock signal (full path) of the first file, used by --upscale-timeskew
+    /// and --clock-align
+    #[arg(long, group = "clock")]
+    clock_signal: Option<String>,
+
+    /// Use the 1 bit signal of the first file changing the most as
+    /// --clock-signal
+    #[arg(long, group = "clock")]
+    infer_clock: bool,
+
+    /// Round the timeskew between reset-synced files up to a multiple of the
+    /// period of --clock-signal, its smallest interval between rising edges
+    #[arg(long, requires = "clock", conflicts_with_all = ["offset", "append"])]
+    upscale_timeskew: bool,
+
+    /// Path to the output merged VCD file, `-` for stdout
+    #[arg(short, long, required_unless_present_any = ["dry_run", "report_skew", "split_output"])]
+    output_file: Option<PathBuf>,
+
+    /// Write each input, shifted by its timeskew, to DIR/<name>_synced.vcd
+    /// instead of merging them, e.g. to open them side by side in a viewer
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output_file", "dry_run", "max_memory"])]
+    split_output: Option<PathBuf>,
+
+    /// Parse and merge the files, print statistics but don't write anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Fail when the timeskew of a file synced on its reset is over this many
+    /// merged timescale units, usually a wrong reset signal
+    #[arg(long, value_name = "TICKS", conflicts_with = "append")]
+    timeskew_limit: Option<u64>,
+
+    /// Fail when the reset of a file isn't released within --max-delta of
+    /// the reset of the first file, e.g. a reset captured on different nets
+    #[arg(long, requires = "max_delta", conflicts_with_all = ["offset", "append"])]
+    check_reset_consistency: bool,
+
+    /// Largest difference between reset releases accepted by
+    /// --check-reset-consistency, in merged timescale units (e.g. a clock period)
+    #[arg(long, value_name = "TICKS", requires = "check_reset_consistency")]
+    max_delta: Option<u64>,
+
+    /// Only print the timeskew of every file, nothing is merged or written
+    #[arg(long)]
+    report_skew: bool,
+
+    /// With --report-skew, exit with 2 when a timeskew is over this many ns
+    #[arg(long, value_name = "NS", requires = "report_skew")]
+    max_skew: Option<u64>,
+
+    /// Print the signals found in only one of the files
     #[arg(short, long)]
-    output_file: PathBuf,
+    verbose: bool,
+
+    /// Don't color the warning and error labels, they are only colored on a
+    /// terminal and without NO_COLOR anyway
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Fail when no signal is found in every file
+    #[arg(long)]
+    require_common_signals: bool,
+
+    /// Only keep signals whose full path (e.g. top.cpu.*.valid) matches this glob
+    #[arg(long)]
+    include: Vec<Pattern>,
+
+    /// Drop signals whose full path matches this glob, wins over --include
+    #[arg(long)]
+    exclude: Vec<Pattern>,
+
+    /// Only keep the signals declared below this scope (dotted path, e.g.
+    /// top.pcie), on top of the ones kept by --include
+    #[arg(long)]
+    include_scope: Vec<String>,
+
+    /// Drop every signal declared below this scope, wins over the includes
+    #[arg(long)]
+    exclude_scope: Vec<String>,
+
+    /// Warn about signals changing more than N times per 1000 timestamps,
+    /// usually glitchy captures
+    #[arg(long, value_name = "N")]
+    warn_noisy_signals: Option<u64>,
+
+    /// Warn about pulses of a signal shorter than this many timescale units
+    /// of its file, with their timestamp and width
+    #[arg(long, value_name = "TICKS")]
+    glitch_threshold: Option<u64>,
+
+    /// Remove the glitches found by --glitch-threshold (never on the reset
+    /// signal)
+    #[arg(long, requires = "glitch_threshold")]
+    filter_glitches: bool,
+
+    /// Move the changes following a timestamp by at most this many merged
+    /// timescale units to that timestamp
+    #[arg(long, value_name = "TICKS")]
+    normalize_timestamps: Option<u64>,
+
+    /// Drop the signals declared in a file but never changing in it (never
+    /// the reset signal)
+    #[arg(long)]
+    drop_inactive_signals: bool,
+
+    /// Drop the signals found by --warn-noisy-signals (never the reset signal)
+    #[arg(long, requires = "warn_noisy_signals")]
+    drop_noisy_signals: bool,
+
+    /// Warn about the signals of the merged trace holding X, with the first
+    /// timestamp they do
+    #[arg(long)]
+    warn_on_x: bool,
+
+    /// Warn about the signals of the merged trace holding Z, with the first
+    /// timestamp they do
+    #[arg(long)]
+    warn_on_z: bool,
+
+    /// Don't warn about X or Z on this signal (full path, repeatable)
+    #[arg(long)]
+    ignore_x_for: Vec<String>,
+
+    /// Fail on suspicious input instead of printing a warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Only write the merged trace from this timestamp on (merged timescale units)
+    #[arg(long)]
+    trim_start: Option<u64>,
+
+    /// Only write the merged trace up to this timestamp (merged timescale units)
+    #[arg(long)]
+    trim_end: Option<u64>,
+
+    /// How signals with the same full path in several files are renamed
+    #[arg(long, value_enum, default_value_t = NameConflict::SuffixIndex)]
+    name_conflict: NameConflict,
+
+    /// Write a JSON summary of the merge to this path
+    #[arg(long)]
+    stats: Option<PathBuf>,
+
+    /// Don't record the inputs and the merge date in a $comment of the
+    /// output header, for reproducible outputs
+    #[arg(long)]
+    no_provenance: bool,
+
+    /// Only check these files for well-formedness, exits with 0 when clean,
+    /// 1 on warnings and 2 on errors
+    #[arg(long, num_args = 1.., exclusive = true)]
+    validate: Vec<PathBuf>,
+
+    /// Only print the signals declared in these files, one per line as
+    /// FILE<tab>PATH<tab>WIDTH<tab>TYPE
+    #[arg(long, num_args = 1.., exclusive = true)]
+    list_signals: Vec<PathBuf>,
+
+    /// Nest the signals of an input file in a scope below the --module-name
+    /// module, as FILE=SCOPE where FILE is the path or the file name of the
+    /// input (repeatable)
+    #[arg(long, value_parser = parse_prefix)]
+    prefix: Vec<(PathBuf, String)>,
+
+    /// Reuse the traces parsed by a previous run from this directory, and
+    /// cache the ones parsed by this run. Entries are keyed by the input path,
+    /// size, modification time and parse options, stale ones are never
+    /// deleted
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Write this signal (full path, as renamed in the output) with its 0 and
+    /// 1 swapped, x and z are kept (repeatable)
+    #[arg(long)]
+    invert_signal: Vec<String>,
+
+    /// Rename the first scope named OLD of every signal path to NEW, as
+    /// OLD=NEW, after --prefix and before duplicate names are resolved
+    /// (repeatable)
+    #[arg(long, value_parser = parse_scope_rename)]
+    scope_rename: Vec<(String, String)>,
+
+    /// Rename a signal of the output, as OLD=NEW where OLD is its full path
+    /// once duplicate names are resolved and NEW a leaf name or a full path
+    /// (repeatable)
+    #[arg(long, value_parser = parse_signal_rename)]
+    rename_signal: Vec<(String, String)>,
+
+    /// CSV file of signal aliases, renamed like --rename-signal after it.
+    /// Each line is full_path,display_name, e.g. top.cpu.r0,CPU:register_0;
+    /// blank lines, lines starting with # and a full_path,display_name
+    /// header are skipped, invalid lines are ignored with a warning
+    #[arg(long, value_name = "PATH")]
+    signal_alias_file: Option<PathBuf>,
+
+    /// Module of the output holding the signals declared in no scope
+    #[arg(long, default_value = "top")]
+    module_name: String,
+
+    /// Nest every root scope of the output in the --module-name module
+    #[arg(long)]
+    wrap_in_top: bool,
+
+    /// Nest the scopes of every file in a scope named after it (its stem or
+    /// --prefix) below the --module-name module, e.g. top.capture1.cpu.clk,
+    /// same as a --prefix of its stem for every file without one
+    #[arg(long)]
+    keep_scope_structure: bool,
+
+    /// Don't copy the $date and $version of the inputs to the output, for
+    /// reproducible outputs
+    #[arg(long)]
+    strip_header_metadata: bool,
+
+    /// Keep the IdCode of every input signal when it isn't already used by
+    /// a previous file, only conflicting signals get a new one
+    #[arg(long)]
+    preserve_id_codes: bool,
+
+    /// Follow every timestamp of the output with a $comment of its time,
+    /// e.g. T=42 ns, to search the file as text
+    #[arg(long)]
+    output_timescale_comment: bool,
+
+    /// Write the signals sorted by full path, so that merges of traces
+    /// declaring them in another order give the same output
+    #[arg(long)]
+    sort_signals: bool,
+
+    /// Parse the written VCD back and check its variables, last timestamp
+    /// and IdCodes, the output is deleted when they don't match
+    #[arg(long, conflicts_with_all = ["split_output", "dry_run", "report_skew"])]
+    verify_merge: bool,
+
+    /// Memory budget in MB: when the inputs may need more (8 bytes per input
+    /// byte), the last file is merged while the output is written instead
+    /// of collecting the merged trace. Ignored with options needing the
+    /// whole merged trace
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<f64>,
+
+    /// Abort when parsing the input files takes longer than this many
+    /// seconds, e.g. so that a corrupt input can't hang a CI job
+    #[arg(long, value_parser = parse_timeout, value_name = "SECONDS")]
+    timeout_seconds: Option<Duration>,
+
+    /// Format of the output file
+    #[arg(long, value_enum, default_value_t = OutputFormat::Vcd)]
+    output_format: OutputFormat,
 }
 
-// Signal name / Id code
-type SignalsCode = Vec<(String, IdCode)>;
-// Time stamp  : [Value Changed]
-type TimestampValues = BTreeMap<u64, Vec<(u32, Value)>>;
+fn parse_width(width : &str) -> std::result::Result<(String, u32), String>
+{
+    match width.split_once('=').map(|(signal, bits)| (signal, bits.parse::<u32>()))
+    {
+        Some((signal, Ok(bits))) if !signal.is_empty() && bits > 0 => Ok((signal.to_string(), bits)),
+        _ => Err(format!("expected SIGNAL=WIDTH, got {}", width)),
+    }
+}
 
-pub struct VCD
+fn parse_signal_rename(rename : &str) -> std::result::Result<(String, String), String>
 {
-    pub timescale_value: u32,
-    pub timescale_unit : TimescaleUnit,
-    pub signals : Vec<String>,
-    pub values : TimestampValues,
-    pub rst_end : u64,
-    pub rst_id : IdCode,
+    match rename.split_once('=')
+    {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() && !new.split('.').any(str::is_empty) =>
+            Ok((old.to_string(), new.to_string())),
+        _ => Err(format!("expected OLD_PATH=NEW_NAME, got {}", rename)),
+    }
 }
 
-impl VCD
+// (full path, display name) pairs of --signal-alias-file
+fn read_signal_aliases(alias_file : &Path) -> Result<Vec<(String, String)>>
 {
-    pub fn new(file_path : &PathBuf, reset_signal : &str) -> Result<VCD>
+    let text = std::fs::read_to_string(alias_file).with_context(|| format!("Can't read --signal-alias-file {}", alias_file.display()))?;
+    let valid = |name : &str| !name.is_empty() && !name.contains(char::is_whitespace) && !name.split('.').any(str::is_empty);
+    let mut aliases = Vec::new();
+    for (i, line) in text.lines().enumerate()
     {
-        let mut parser = Parser::new(BufReader::new(File::open(file_path)?));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line == "full_path,display_name")
+        {
+            continue;
+        }
+        match line.split(',').map(|field| field.trim().trim_matches('"')).collect::<Vec<_>>()[..]
+        {
+            [path, name] if valid(path) && valid(name) => aliases.push((path.to_string(), name.to_string())),
+            _ => warning!("Ignoring line {} of {}, expected full_path,display_name, got {}", i + 1, alias_file.display(), line),
+        }
+    }
+    Ok(aliases)
+}
 
-        let parsed_header = parser.parse_header()?;
-        let (timescale_value, timescale_unit) = parsed_header.timescale.context("Timescale not found in VCD 1")?;
-        let split = reset_signal.split(".").collect::<Vec<&str>>();
-        let rst_id = parsed_header.find_var(&split).context("Reset signal not found in vcd 1")?.code;
-        let signals_id = signals(&parsed_header.items);
-        let (values, rst_end) = collect_values(&signals_id, &mut parser, rst_id);
-        println!("Reset signal end found at : {}", rst_end);
-        //Signal are already splitted
-        //let signal_name = signal_name.split('.').next_back().unwrap_or(&signal_name);
-        let signals : Vec<String> = signals_id.into_iter().map(|(sig_name, _sig_id)| (sig_name)).collect();
-        Ok(VCD{ timescale_value, timescale_unit, signals, values, rst_id, rst_end })
+fn parse_timeout(seconds : &str) -> std::result::Result<Duration, String>
+{
+    match seconds.parse::<f64>().ok().map(Duration::try_from_secs_f64)
+    {
+        Some(Ok(timeout)) if !timeout.is_zero() => Ok(timeout),
+        _ => Err(format!("expected a positive number of seconds, got {}", seconds)),
     }
+}
 
-    pub fn merge(&mut self, vcd : VCD)
+fn parse_prefix(prefix : &str) -> std::result::Result<(PathBuf, String), String>
+{
+    match prefix.split_once('=')
     {
-        println!("Merging files with a timeskew of {} {}",
-                 self.rst_end - vcd.rst_end,
-                 self.timescale_unit);
-        let timeskew = self.rst_end - vcd.rst_end;
+        Some((file, scope)) if !file.is_empty() && !scope.is_empty() => Ok((PathBuf::from(file), scope.to_string())),
+        _ => Err(format!("expected FILE=SCOPE, got {}", prefix)),
+    }
+}
 
-        let signals_id_start = self.signals.len() as u32;
-        //XXX we should remove all 'none' signals
-        //created by acquisiton tool
+fn parse_scope_rename(rename : &str) -> std::result::Result<(String, String), String>
+{
+    match rename.split_once('=')
+    {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() && !old.contains('.') && !new.contains('.') =>
+            Ok((old.to_string(), new.to_string())),
+        _ => Err(format!("expected OLD=NEW scope names, got {}", rename)),
+    }
+}
 
-        // Handle duplicate signals by appending "_2" to the signal name
-        for vcd_signal in &vcd.signals
-        {
-            match self.signals.contains(vcd_signal)
-            {
-                true => self.signals.push(format!("{}_2", vcd_signal)),
-                false => self.signals.push(vcd_signal.clone()),
-            }
-        }
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Align two files on their reset and report the timestamps where their
+    /// common signals differ, exits with 1 when they do
+    Diff(DiffArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Path to the two VCD files, e.g. a reference simulation and a capture
+    #[arg(num_args = 2, required = true)]
+    vcd_files: Vec<PathBuf>,
 
-        // Adjust timestamps and merge values
-        for (timestamp, values) in vcd.values.iter()
+    /// Name of the reset signal to resynchronize on
+    #[arg(short, long)]
+    reset_signal: String,
+
+    /// Reset is active high, sync on its falling edge instead of its rising edge
+    #[arg(long)]
+    reset_active_high: bool,
+
+    /// Full path of a signal to compare (repeatable)
+    #[arg(long, required_unless_present_any = ["all", "write_diff"])]
+    signal: Vec<String>,
+
+    /// Compare every signal declared with the same full path in both files
+    #[arg(long, conflicts_with = "signal")]
+    all: bool,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Also write a VCD holding only the changes of the second file that
+    /// differ from the first one, and the signals only in the second file
+    #[arg(long)]
+    write_diff: Option<PathBuf>,
+
+    /// With --write-diff, also write the signals only in the first file
+    #[arg(long, requires = "write_diff")]
+    include_reference_only: bool,
+}
+
+// Returns the exit code of the diff subcommand
+fn run_diff(args : DiffArgs) -> Result<i32>
+{
+    let options = ParseOptions {
+        rst_polarity : match args.reset_active_high
         {
-            let synced = timestamp + timeskew;
-            let entry = self.values.entry(synced).or_default();
-            for (id, value) in values
-            {
-                entry.push((*id + signals_id_start, *value));
-            }
+            true => ResetPolarity::ActiveHigh,
+            false => ResetPolarity::ActiveLow,
+        },
+        ..ParseOptions::default()
+    };
+    let mut vcds = Vec::new();
+    for vcd_file in &args.vcd_files
+    {
+        eprintln!("Parsing file : {}", vcd_file.display());
+        vcds.push(VCD::new(vcd_file, &args.reset_signal, &options)?);
+    }
+    let normalizer = TimescaleNormalizer::new(&vcds)?;
+    for vcd in vcds.iter_mut()
+    {
+        normalizer.normalize(vcd)?;
+    }
+    if let Some(diff_file) = &args.write_diff
+    {
+        eprintln!("Writing differences in : {}", diff_file.display());
+        write_vcd_diff(&vcds[0], &vcds[1], diff_file, args.include_reference_only)?;
+    }
+    let differences = diff(&vcds[0], &vcds[1], &args.signal)?;
+    if args.json
+    {
+        println!("{}", serde_json::to_string_pretty(&differences)?);
+    }
+    else
+    {
+        for difference in &differences
+        {
+            println!("#{} {} : {} != {}",
+                     difference.timestamp,
+                     difference.signal,
+                     difference.left.as_deref().unwrap_or("-"),
+                     difference.right.as_deref().unwrap_or("-"));
         }
+        eprintln!("{} differences", differences.len());
+    }
+    Ok(if differences.is_empty() { 0 } else { 1 })
+}
 
-        // Initialize all signals to 0 at timestamp 0 to avoid errors in GTKWavee
-        let mut init = Vec::new();
-        for id in 0..self.signals.len()
+// --split-output, vcds must already be normalized
+fn write_split(vcd_files : &[PathBuf], vcds : Vec<VCD>, clock_period : u64, dir : &Path, options : &WriteOptions) -> Result<()>
+{
+    let mut outputs = Vec::new();
+    for vcd_file in vcd_files
+    {
+        let name = vcd_file.file_name().context("No file name")?.to_string_lossy();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        let output_file = dir.join(format!("{}_synced.vcd", name.strip_suffix(".vcd").unwrap_or(name)));
+        if let Some(previous) = outputs.iter().position(|output| *output == output_file)
         {
-            // set it low by default ?
-            init.push((id as u32, Value::V0));
+            bail!("{} and {} would both be written to {}", vcd_files[previous].display(), vcd_file.display(), output_file.display());
         }
-        self.values.insert(0, init);
+        outputs.push(output_file);
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let timeskews = rounded_timeskews(&vcds, clock_period);
+    for ((mut vcd, timeskew), output_file) in vcds.into_iter().zip(timeskews).zip(outputs)
+    {
+        eprintln!("Writing trace shifted by {} in : {}",
+                  display_time(timeskew, vcd.timescale_value, vcd.timescale_unit), output_file.display());
+        vcd.shift(timeskew)?;
+        write_vcd_to_file(vcd, &output_file, options)?;
     }
+    Ok(())
 }
 
-fn signals(items: &[ScopeItem]) -> SignalsCode {
-    let mut results = Vec::new();
-
-    fn recursive_collect(
-        items: &[ScopeItem],
-        current_scope: &str,
-        results: &mut Vec<(String, IdCode)>,
-    ) {
-        for item in items {
-            match item {
-                ScopeItem::Var(var) => {
-                    let full_reference = if current_scope.is_empty() {
-                        var.reference.clone()
-                    } else {
-                        //format!("{}.{}", current_scope, var.reference)
-                        var.reference.clone()
-                    };
-                    results.push((full_reference, var.code));
-                }
-                ScopeItem::Scope(scope) => {
-                    let new_scope = if current_scope.is_empty() {
-                        scope.identifier.clone()
-                    } else {
-                        format!("{}.{}", current_scope, scope.identifier)
-                    };
-                    recursive_collect(&scope.items, &new_scope, results);
-                }
-                _ => (),
-            }
+// Per input statistics for --dry-run, vcds must already be normalized
+fn print_inputs(vcd_files : &[PathBuf], vcds : &[VCD], clock_period : u64)
+{
+    let raw_timeskews = timeskews(vcds);
+    for ((vcd_file, vcd), (timeskew, raw_timeskew)) in vcd_files.iter().zip(vcds).zip(rounded_timeskews(vcds, clock_period).into_iter().zip(raw_timeskews))
+    {
+        let time = |timestamp| display_time(timestamp, vcd.timescale_value, vcd.timescale_unit);
+        let sync = match vcd.sync
+        {
+            SyncPoint::ResetSignal(rst_end) => format!("reset end at {}", time(rst_end)),
+            sync => sync.to_string(),
+        };
+        let active = vcd.active_signal_count();
+        print!("{} : {} signals{}, {}, timeskew {}",
+               vcd_file.display(),
+               vcd.signals.len(),
+               match active == vcd.signals.len()
+               {
+                   true => String::new(),
+                   false => format!(" ({} changing)", active),
+               },
+               sync,
+               time(timeskew));
+        match timeskew == raw_timeskew
+        {
+            true => println!(),
+            false => println!(" (raw timeskew {})", time(raw_timeskew)),
         }
     }
-
-    recursive_collect(items, "", &mut results);
-    results
 }
 
-fn collect_values<T>(signals: &SignalsCode, vcd: &mut Parser<T>, id_code : IdCode) -> (TimestampValues, u64)
-where
-    T: std::io::BufRead,
+// Prints the timeskew of every file for --report-skew, returns its exit code
+fn print_skews(vcd_files : &[PathBuf], vcds : &[VCD], clock_period : u64, max_skew : Option<u64>) -> i32
 {
-    let mut values: TimestampValues = TimestampValues::new();
-    let mut current_timestamp = 0;
-    let mut reset_timestamp = 0;
+    let mut exceeded = false;
+    for ((vcd_file, vcd), timeskew) in vcd_files.iter().zip(vcds).zip(rounded_timeskews(vcds, clock_period))
+    {
+        let ns = timeskew as f64 * vcd.timescale_value as f64 * 1e9 / vcd.timescale_unit.divisor() as f64;
+        println!("{} : timeskew {} x {} {}, {} ns", vcd_file.display(), timeskew, vcd.timescale_value, vcd.timescale_unit, ns);
+        exceeded |= max_skew.is_some_and(|max_skew| ns > max_skew as f64);
+    }
+    match exceeded
+    {
+        true => 2,
+        false => 0,
+    }
+}
 
-    let mut id_map : HashMap<IdCode, u32> = HashMap::new();
+// Prints the issues of every file, returns the exit code of --validate
+fn print_validation(vcd_files : &[PathBuf]) -> i32
+{
+    let mut worst = None;
+    for vcd_file in vcd_files
+    {
+        let report = validate(vcd_file);
+        if report.issues.is_empty()
+        {
+            println!("{} : ok", vcd_file.display());
+        }
+        for issue in &report.issues
+        {
+            let severity = match issue.severity
+            {
+                Severity::Warning => diagnostic::label(Level::Warning, Stream::Stdout),
+                Severity::Error => diagnostic::label(Level::Error, Stream::Stdout),
+            };
+            println!("{} : {}: {}", vcd_file.display(), severity, issue.message);
+        }
+        worst = worst.max(report.severity());
+    }
+    match worst
+    {
+        None => 0,
+        Some(Severity::Warning) => 1,
+        Some(Severity::Error) => 2,
+    }
+}
 
-    for (i, (_, id_code)) in signals.iter().enumerate()
+// X or Z found in the merged trace, one line per signal
+fn warn_states(merged : &VCD, state : Value, ignored : &[String])
+{
+    let found : Vec<(String, u64)> = merged.first_timestamps_with(state).into_iter()
+        .map(|(id, timestamp)| (merged.signals[id as usize].full_name(), timestamp))
+        .filter(|(name, _)| !ignored.contains(name))
+        .collect();
+    if found.is_empty()
+    {
+        return;
+    }
+    warning!("Signals holding {} in the merged trace :", state);
+    for (name, timestamp) in found
     {
-        id_map.insert(*id_code, i as u32);
+        eprintln!("  {} from {}", name, display_time(timestamp, merged.timescale_value, merged.timescale_unit));
     }
+}
+
+// Names the flag getting past a parse error, which the library can't
+fn with_flag_hint(error : VcdSyncError) -> anyhow::Error
+{
+    let hint = match error
+    {
+        VcdSyncError::TimescaleNotFound { .. } => "No timescale to scale the file to, see --default-timescale-unit",
+        VcdSyncError::ResetNeverDeasserted { .. } | VcdSyncError::ResetNotSeen { .. } =>
+            "No reset release to sync on, use --allow-no-reset to sync on t=0",
+        _ => return error.into(),
+    };
+    anyhow::Error::from(error).context(hint)
+}
 
-    for cmd in vcd.into_iter().flatten()
+// Every asserted width is checked before failing, so that a single run
+// lists all the mismatches
+fn check_widths(vcd_files : &[PathBuf], vcds : &[VCD], asserted : &[(String, u32)]) -> Result<()>
+{
+    let mut failures = Vec::new();
+    for (name, width) in asserted
     {
-        match cmd
+        let mut found = false;
+        for (vcd_file, vcd) in vcd_files.iter().zip(vcds)
         {
-            ChangeScalar(id, value) =>
+            for signal in vcd.signals.iter().filter(|signal| signal.full_name() == *name)
             {
-                values.entry(current_timestamp)
-                    .or_default()
-                    .push((id_map[&id], value));
-                //Here reset is active low
-                //so we wait for last reset == 1 value
-                //because it mean reset is not active anymore
-                //then we get that timestamp to use it to sync
-                //the traces
-                if id == id_code && value == true.into()
+                found = true;
+                if signal.width != *width
                 {
-                    reset_timestamp = current_timestamp;
+                    failures.push(format!("{} is {} bits wide in {}, {} asserted", name, signal.width, vcd_file.display(), width));
                 }
-            },
-            Timestamp(timestamp) =>
-            {
-              current_timestamp = timestamp;
-            },
-            // XXX collect other value type ?
-            _ => (),
+            }
+        }
+        if !found
+        {
+            failures.push(format!("{} is not declared in any file, {} bits asserted", name, width));
         }
     }
+    if !failures.is_empty()
+    {
+        bail!("{} signal width assertions failed :\n  {}", failures.len(), failures.join("\n  "));
+    }
+    Ok(())
+}
 
-    (values, reset_timestamp)
+// Traces without a single signal in common were most likely not meant to
+// be merged, e.g. a wrong file on the command line
+fn check_overlap(merged : &VCD, vcd : &VCD, vcd_file : &Path, strict : bool) -> Result<()>
+{
+    if vcd.common_signals(merged).next().is_some()
+    {
+        return Ok(());
+    }
+    if strict
+    {
+        bail!("No signal of {} is found in the previous files", vcd_file.display());
+    }
+    let first = |vcd : &VCD| vcd.signals.iter().take(5).map(|signal| signal.full_name()).collect::<Vec<_>>().join(", ");
+    warning!("No signal of {} is found in the previous files, the merged trace may not be meaningful", vcd_file.display());
+    eprintln!("  previous files : {}", first(merged));
+    eprintln!("  {} : {}", vcd_file.display(), first(vcd));
+    Ok(())
 }
 
-fn write_vcd(merged : VCD, output_file : &PathBuf) -> Result<()>
+fn main()
 {
-    let mut writer = vcd::Writer::new(BufWriter::new(File::create(output_file)?));
-    writer.timescale(merged.timescale_value, merged.timescale_unit)?;
+    if let Err(error) = run()
+    {
+        diagnostic::error(format_args!("{:?}", error));
+        std::process::exit(1);
+    }
+}
 
-    //XXX get top module as arg ?
-    //use file name so it's easier to know where signal came from ?
-    writer.add_module("top")?;
+fn run() -> Result<()>
+{
+    let mut args = Args::parse();
+    if args.no_color
+    {
+        diagnostic::set_color(false);
+    }
+    if let Some(Command::Diff(diff_args)) = args.command
+    {
+        std::process::exit(run_diff(diff_args)?);
+    }
+    if !args.list_signals.is_empty()
+    {
+        for vcd_file in &args.list_signals
+        {
+            for signal in declared_signals(vcd_file)?
+            {
+                println!("{}\t{}\t{}\t{}", vcd_file.display(), signal.full_name(), signal.width, signal.var_type);
+            }
+        }
+        return Ok(());
+    }
+    if !args.validate.is_empty()
+    {
+        std::process::exit(print_validation(&args.validate));
+    }
+    let options = ParseOptions {
+        rst_polarity : match args.reset_active_high
+        {
+            true => ResetPolarity::ActiveHigh,
+            false => ResetPolarity::ActiveLow,
+        },
+        filter : SignalFilter {
+            include : args.include,
+            exclude : args.exclude,
+            include_scopes : args.include_scope,
+            exclude_scopes : args.exclude_scope,
+        },
+        strict : args.strict,
+        allow_no_reset : args.allow_no_reset,
+        noisy_threshold : args.warn_noisy_signals,
+        drop_noisy : args.drop_noisy_signals,
+        drop_inactive : args.drop_inactive_signals,
+        glitch_threshold : args.glitch_threshold,
+        filter_glitches : args.filter_glitches,
+        reset_search : args.reset_signal_search,
+        reset_fallbacks : args.reset_signal_fallback,
+        default_timescale : args.default_timescale_unit.map(|unit| (args.default_timescale_value, unit)),
+        force_width : args.force_bit_width,
+    };
 
-    let mut signals_map : HashMap<u32, IdCode>  =  HashMap::new();
-    for (i, signal_name) in merged.signals.into_iter().enumerate()
+    let align_mode = args.align_mode.unwrap_or_default();
+    match (align_mode, &args.reset_signal)
     {
-        signals_map.insert(i as u32,  writer.add_wire(1, &signal_name)?);
+        (AlignMode::ResetSignal, None) if args.offset.is_empty() && !args.append =>
+            bail!("--reset-signal is required to sync on the reset signal"),
+        (AlignMode::FirstEvent, Some(_)) => bail!("--reset-signal can't be used with --align-mode first_event"),
+        _ => (),
     }
 
-    writer.upscope()?;
-    writer.enddefinitions()?;
+    // Without any file there is no first one, and no offset is expected
+    if !args.offset.is_empty() && args.vcd_files.len().checked_sub(1) != Some(args.offset.len())
+    {
+        bail!("Expected {} offsets (one per file after the first one), got {}",
+              args.vcd_files.len().saturating_sub(1),
+              args.offset.len());
+    }
 
-    for (timestamp, values) in merged.values.into_iter()
+    // FST files are patched after being written, which needs a real file
+    if matches!(args.output_format, OutputFormat::Fst) && args.output_file.as_ref().is_some_and(|output_file| output_file.as_os_str() == "-")
     {
-        writer.timestamp(timestamp)?;
-        for (id, value) in values
+        bail!("FST output can't be written to stdout");
+    }
+    if args.verify_merge && (!matches!(args.output_format, OutputFormat::Vcd) || args.output_file.as_ref().is_some_and(|output_file| output_file.as_os_str() == "-"))
+    {
+        bail!("--verify-merge only checks VCD output files");
+    }
+
+    // Matched before parsing so that a typo doesn't cost a full parse
+    let mut prefixes = Vec::new();
+    for (file, scope) in &args.prefix
+    {
+        let i = args.vcd_files.iter()
+            .position(|vcd_file| vcd_file == file || vcd_file.file_name() == Some(file.as_os_str()))
+            .with_context(|| format!("--prefix {} doesn't match any input file", file.display()))?;
+        prefixes.push((i, scope.clone()));
+    }
+
+    // Estimated before parsing, the streaming merge only helps if picked
+    // before memory runs out
+    let mut stream = false;
+    if let Some(max_memory) = args.max_memory
+    {
+        let estimated = estimated_memory(&args.vcd_files)?;
+        if estimated as f64 > max_memory * 1024.0 * 1024.0
         {
-          let id_code = signals_map[&id];
-          writer.change_scalar(id_code, value)?;
+            let blocker = [(args.dry_run, "--dry-run"), (args.stats.is_some(), "--stats"), (args.clock_align, "--clock-align"),
+                           (args.warn_on_x, "--warn-on-x"), (args.warn_on_z, "--warn-on-z"),
+                           (args.normalize_timestamps.is_some(), "--normalize-timestamps"),
+                           (args.verify_merge, "--verify-merge"), (!matches!(args.output_format, OutputFormat::Vcd), "--output-format")]
+                .into_iter()
+                .find_map(|(set, option)| set.then_some(option));
+            match blocker
+            {
+                Some(option) => warning!("Merge may need {:.1} MB, over --max-memory, but {} needs the whole merged trace",
+                                         estimated as f64 / (1024.0 * 1024.0), option),
+                None =>
+                {
+                    eprintln!("Merge may need {:.1} MB, over --max-memory, streaming the last file", estimated as f64 / (1024.0 * 1024.0));
+                    stream = true;
+                },
+            }
         }
     }
 
-    Ok(())
-}
-
-fn main()  -> Result<()>
-{
-    let args = Args::parse();
+    let parse_start = Instant::now();
+    // A parser can't be interrupted, the whole process is stopped instead.
+    // Dropping `parse_done` stops the watchdog
+    let parse_done = args.timeout_seconds.map(|timeout|
+    {
+        let (parse_done, parse_finished) = mpsc::channel::<()>();
+        std::thread::spawn(move ||
+        {
+            if let Err(RecvTimeoutError::Timeout) = parse_finished.recv_timeout(timeout)
+            {
+                diagnostic::error(format_args!("Parsing didn't finish within --timeout-seconds ({} s), aborting", timeout.as_secs_f64()));
+                std::process::exit(1);
+            }
+        });
+        parse_done
+    });
+    // Files are independent, each one is parsed on its own thread
+    let (reset_signal, offsets, append, options) = (&args.reset_signal, &args.offset, args.append, &options);
+    let first_event = align_mode == AlignMode::FirstEvent;
+    let cache_dir = args.cache_dir.as_deref();
+    let mut vcds = parse_all(&args.vcd_files, |i, vcd_file|
+    {
+        let offset = i.checked_sub(1).and_then(|i| offsets.get(i)).copied().unwrap_or(0);
+        let parse = ||
+        {
+            eprintln!("Parsing file : {}", vcd_file.display());
+            match reset_signal
+            {
+                Some(reset_signal) => VCD::new(vcd_file, reset_signal, options),
+                None if first_event => VCD::first_event(vcd_file, options),
+                None if append => VCD::appended(vcd_file, options),
+                None => VCD::with_offset(vcd_file, offset, options),
+            }
+        };
+        match cache_dir
+        {
+            // Everything the parse depends on besides the file itself
+            Some(cache_dir) => cached_parse(cache_dir, vcd_file, &(reset_signal, first_event, append, offset, options), parse),
+            None => parse(),
+        }
+    }).map_err(with_flag_hint)?;
+    drop(parse_done);
+    check_widths(&args.vcd_files, &vcds, &args.assert_signal_width)?;
 
-    let mut vcd_files = args.vcd_files.iter();
-    let main_vcd_file = vcd_files.next().unwrap();
-    println!("Parsing file : {}", main_vcd_file.display());
-    let mut main_vcd = VCD::new(main_vcd_file, &args.reset_signal)?;
+    for (i, scope) in prefixes
+    {
+        vcds[i].sources[0].prefix = Some(scope);
+    }
+    if args.keep_scope_structure
+    {
+        for source in vcds.iter_mut().map(|vcd| &mut vcd.sources[0])
+        {
+            if source.prefix.is_none()
+            {
+                source.prefix = Some(source.stem());
+            }
+        }
+    }
 
-    for current_vcd_file in vcd_files
+    let normalizer = match args.force_timescale_unit
+    {
+        Some(unit) => TimescaleNormalizer::forced(args.force_timescale_value, unit)?,
+        None => TimescaleNormalizer::new(&vcds)?,
+    };
+    for vcd in vcds.iter_mut()
     {
-        println!("Parsing file : {}", current_vcd_file.display());
-        let mut current_vcd = VCD::new(current_vcd_file, &args.reset_signal)?;
+        normalizer.normalize(vcd)?;
+    }
 
-        if main_vcd.timescale_value != current_vcd.timescale_value
+    let parse_seconds = parse_start.elapsed().as_secs_f64();
+
+    if args.verbose || args.require_common_signals
+    {
+        let signal_sets = SignalSets::new(&vcds);
+        if args.verbose
         {
-            bail!("Error: Timescale values are different: {} {}",
-                  main_vcd.timescale_value,
-                  current_vcd.timescale_value);
+            for (vcd_file, unique) in args.vcd_files.iter().zip(&signal_sets.unique).filter(|(_, unique)| !unique.is_empty())
+            {
+                eprintln!("Only in {} : {}", vcd_file.display(), unique.join(", "));
+            }
         }
+        if args.require_common_signals && signal_sets.common.is_empty()
+        {
+            bail!("No signal is found in every file");
+        }
+    }
 
-        if main_vcd.timescale_unit != current_vcd.timescale_unit
+    if args.infer_clock
+    {
+        let clock = vcds[0].infer_clock()
+            .with_context(|| format!("No 1 bit signal changes in {}, no clock to infer", args.vcd_files[0].display()))?
+            .full_name();
+        match vcds[0].clock_period(&clock)
         {
-            bail!("Error: Timescale units are different: {} {}",
-                  main_vcd.timescale_unit,
-                  current_vcd.timescale_unit);
+            Some(period) => eprintln!("Inferred clock : {}, period {}", clock,
+                                      display_time(period, vcds[0].timescale_value, vcds[0].timescale_unit)),
+            None => warning!("Inferred clock {} rises less than twice, it has no period", clock),
         }
+        args.clock_signal = Some(clock);
+    }
 
-        println!("Resyncing and merging traces");
-        main_vcd = match main_vcd.rst_end > current_vcd.rst_end
+    // Detected on the reference trace, after normalization so that it is in
+    // merged timescale units
+    let clock_period = match (&args.clock_signal, args.upscale_timeskew)
+    {
+        (Some(clock_signal), true) =>
         {
-          true =>  { main_vcd.merge(current_vcd); main_vcd }
-          false => { current_vcd.merge(main_vcd); current_vcd }
+            let clock_period = vcds[0].clock_period(clock_signal)
+                .with_context(|| format!("No clock period found for {} in {}, it needs at least two rising edges",
+                                         clock_signal, args.vcd_files[0].display()))?;
+            eprintln!("Clock period of {} : {}", clock_signal,
+                      display_time(clock_period, vcds[0].timescale_value, vcds[0].timescale_unit));
+            clock_period
+        },
+        _ => 1,
+    };
+
+    if let Some(limit) = args.timeskew_limit
+    {
+        for ((vcd_file, vcd), timeskew) in args.vcd_files.iter().zip(&vcds).zip(timeskews(&vcds))
+        {
+            if timeskew > limit
+            {
+                bail!("Timeskew of {} is {} ticks ({}), over the --timeskew-limit of {}, is the reset signal right?",
+                      vcd_file.display(), timeskew,
+                      display_time(timeskew, vcd.timescale_value, vcd.timescale_unit), limit);
+            }
+        }
+    }
+    if let (true, Some(max_delta), Some(reference)) = (args.check_reset_consistency, args.max_delta, vcds.first())
+    {
+        for (vcd_file, vcd) in args.vcd_files.iter().zip(&vcds).skip(1)
+        {
+            if let (SyncPoint::ResetSignal(reference_end), SyncPoint::ResetSignal(rst_end)) = (reference.sync, vcd.sync)
+            {
+                let delta = reference_end.abs_diff(rst_end);
+                if delta > max_delta
+                {
+                    bail!("Reset of {} is released {} away from the one of {}, over the --max-delta of {}, do both files capture the same reset?",
+                          vcd_file.display(), display_time(delta, vcd.timescale_value, vcd.timescale_unit),
+                          args.vcd_files[0].display(), max_delta);
+                }
+            }
+        }
+    }
+    if args.report_skew
+    {
+        std::process::exit(print_skews(&args.vcd_files, &vcds, clock_period, args.max_skew));
+    }
+    if args.dry_run
+    {
+        print_inputs(&args.vcd_files, &vcds, clock_period);
+    }
+    if let Some(dir) = &args.split_output
+    {
+        let options = WriteOptions {
+            trim_start : args.trim_start,
+            trim_end : args.trim_end,
+            provenance : !args.no_provenance,
+            module_name : Some(args.module_name.clone()),
+            wrap_in_top : args.wrap_in_top,
+            strip_header_metadata : args.strip_header_metadata,
+            preserve_id_codes : args.preserve_id_codes,
+            timestamp_comments : args.output_timescale_comment,
+            sort_signals : args.sort_signals,
+            ..WriteOptions::default()
         };
+        return write_split(&args.vcd_files, vcds, clock_period, dir, &options);
     }
+    let mut stats = match args.stats
+    {
+        Some(_) => Some(MergeStats::new(&vcds, clock_period)?),
+        None => None,
+    };
 
-    println!("Writing merged trace in : {}", args.output_file.display());
-    write_vcd(main_vcd, &args.output_file)?;
-    //XXX add features to write FST directly via fstlib ?
-    //write_fst(merged, &args.output_file)?;
+    // Traces are folded in command-line order, merge itself keeps every
+    // trace aligned on the latest sync point
+    let merge_start = Instant::now();
+    // The streamed file is merged when writing
+    let streamed = match stream
+    {
+        true => vcds.pop(),
+        false => None,
+    };
+    let mut vcds = vcds.into_iter();
+    let mut main_vcd = vcds.next().context("No VCD file to merge")?;
+    let mut merged = false;
+    for (current_vcd, vcd_file) in vcds.zip(&args.vcd_files[1..])
+    {
+        check_overlap(&main_vcd, &current_vcd, vcd_file, args.strict)?;
+        eprintln!("Resyncing and merging traces");
+        main_vcd.merge_rounded(current_vcd, clock_period)?;
+        merged = true;
+    }
+    let initialize_to = match args.keep_x_state
+    {
+        true => Some(Value::X),
+        false => args.initialize_to,
+    };
+    let init_strategy = args.init_strategy;
+    let init = move |var_type| match (init_strategy, initialize_to)
+    {
+        (Some(strategy), _) => strategy.value(var_type),
+        (None, Some(value)) => value,
+        (None, None) => Value::V0,
+    };
+    if merged && streamed.is_none() && (init_strategy.is_some() || initialize_to.is_some())
+    {
+        main_vcd.initialize_with(init);
+    }
+    if let Some(clock) = args.clock_signal.as_ref().filter(|_| args.clock_align)
+    {
+        let moved = main_vcd.clock_align(clock).with_context(|| format!("Clock {} not found or never rising", clock))?;
+        eprintln!("Aligned {} value changes on the rising edges of {}", moved, clock);
+    }
+    if let Some(window) = args.normalize_timestamps
+    {
+        let merged_away = merge_zero_duration_timestamps(&mut main_vcd.values, window);
+        eprintln!("Merged {} timestamps into one at most {} earlier", merged_away,
+                  display_time(window, main_vcd.timescale_value, main_vcd.timescale_unit));
+    }
+    for (warn, state) in [(args.warn_on_x, Value::X), (args.warn_on_z, Value::Z)]
+    {
+        if warn
+        {
+            warn_states(&main_vcd, state, &args.ignore_x_for);
+        }
+    }
+    if let Some(stats) = stats.as_mut()
+    {
+        stats.merged_signal_count = main_vcd.signals.len();
+        stats.merged_timestamp_count = main_vcd.values.len();
+        stats.parse_seconds = parse_seconds;
+        stats.merge_seconds = merge_start.elapsed().as_secs_f64();
+    }
+
+    let output_file = match args.output_file
+    {
+        Some(output_file) if !args.dry_run => output_file,
+        _ =>
+        {
+            println!("Merged trace : {} signals, {} timestamps, ~{} bytes as VCD",
+                     main_vcd.signals.len(),
+                     main_vcd.values.len(),
+                     main_vcd.estimated_vcd_size());
+            print!("{}", main_vcd);
+            println!("Dry run, nothing written");
+            if let (Some(stats), Some(stats_file)) = (stats, &args.stats)
+            {
+                stats.write(stats_file)?;
+            }
+            return Ok(());
+        }
+    };
+
+    let mut signal_renames = args.rename_signal;
+    if let Some(alias_file) = &args.signal_alias_file
+    {
+        signal_renames.extend(read_signal_aliases(alias_file)?);
+    }
+    eprintln!("Writing merged trace in : {}", output_file.display());
+    let write_options = WriteOptions {
+        trim_start : args.trim_start,
+        trim_end : args.trim_end,
+        name_conflict : args.name_conflict,
+        provenance : !args.no_provenance,
+        invert : args.invert_signal,
+        scope_renames : args.scope_rename,
+        signal_renames,
+        module_name : Some(args.module_name),
+        wrap_in_top : args.wrap_in_top,
+        strip_header_metadata : args.strip_header_metadata,
+        preserve_id_codes : args.preserve_id_codes,
+        timestamp_comments : args.output_timescale_comment,
+        sort_signals : args.sort_signals,
+    };
+    if let Some(streamed) = streamed
+    {
+        let vcd_file = args.vcd_files.last().context("No VCD file to merge")?;
+        check_overlap(&main_vcd, &streamed, vcd_file, args.strict)?;
+        eprintln!("Resyncing and merging traces");
+        let output = create_output(&output_file)?;
+        main_vcd.merge_stream_with(streamed, clock_period, init, output, &write_options)?;
+        return Ok(());
+    }
+    let merge_check = args.verify_merge.then(|| MergeCheck::new(&main_vcd, &write_options));
+    let write_start = Instant::now();
+    match args.output_format
+    {
+        OutputFormat::Vcd => write_vcd_to_file(main_vcd, &output_file, &write_options)?,
+        OutputFormat::Fst => write_fst(main_vcd, &output_file, &write_options)?,
+        OutputFormat::Csv => write_csv(main_vcd, &output_file, &write_options)?,
+        OutputFormat::Json => write_json(main_vcd, &output_file, &write_options)?,
+    }
+
+    if let Some(merge_check) = merge_check
+    {
+        eprintln!("Verifying merged trace");
+        if let Err(error) = merge_check.check(&output_file)
+        {
+            // Better no output than a corrupt one
+            std::fs::remove_file(&output_file)?;
+            return Err(error.into());
+        }
+    }
+
+    if let (Some(mut stats), Some(stats_file)) = (stats, &args.stats)
+    {
+        stats.write_seconds = Some(write_start.elapsed().as_secs_f64());
+        stats.write(stats_file)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,28 @@
+//! Parsing progress bars on stderr, behind the `progress` feature.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressBarIter, ProgressFinish, ProgressStyle};
+
+// Files are parsed in parallel, their bars are stacked instead of
+// overwriting each other
+fn bars() -> &'static MultiProgress
+{
+    static BARS : OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Wraps `file` so that reading it moves a progress bar named after `file_path`
+pub(crate) fn tracked(file : File, file_path : &Path) -> std::io::Result<ProgressBarIter<File>>
+{
+    let len = file.metadata()?.len();
+    // The template is a constant, it can't fail to parse
+    let style = ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} ({eta} left)")
+        .expect("valid progress template")
+        .progress_chars("=> ");
+    let name = file_path.file_name().unwrap_or(file_path.as_os_str()).to_string_lossy().into_owned();
+    let bar = bars().add(ProgressBar::new(len).with_style(style).with_message(name).with_finish(ProgressFinish::AndLeave));
+    Ok(bar.wrap_read(file))
+}
@@ -0,0 +1,160 @@
+//! Octal (`o777`) and hexadecimal (`hDEAD`) vector changes, which some
+//! tools emit but the vcd crate only reads in binary.
+//!
+//! Traces are read through [`RadixReader`], which rewrites these changes to
+//! binary before they reach the parser: `o17 #` is read as `b001111 #`. Every
+//! other change, binary vectors, reals and strings included, is passed
+//! through as-is.
+
+use std::io::{self, BufRead, Read};
+
+/// Line by line rewrite of the value changes following `$enddefinitions`
+pub(crate) struct RadixReader<R>
+{
+    inner : R,
+    line : Vec<u8>,
+    pos : usize,
+    in_body : bool,
+    in_comment : bool,
+    // The IdCode following a vector, real or string value, e.g. the hA of
+    // `b1010 hA`, is never a value
+    expect_id : bool,
+}
+
+impl<R : BufRead> RadixReader<R>
+{
+    pub(crate) fn new(inner : R) -> RadixReader<R>
+    {
+        RadixReader { inner, line : Vec::new(), pos : 0, in_body : false, in_comment : false, expect_id : false }
+    }
+
+    fn rewrite_line(&mut self)
+    {
+        if !self.in_body
+        {
+            // The header is left alone, a scope could be named h0
+            self.in_body = self.line.windows(15).any(|window| window == b"$enddefinitions");
+            return;
+        }
+
+        // Most lines can't hold an o or h change, only a dangling vector
+        // value has to be looked for
+        if !self.in_comment && !self.line.iter().any(|byte| matches!(byte, b'o' | b'O' | b'h' | b'H' | b'$'))
+        {
+            for token in self.line.split(u8::is_ascii_whitespace).filter(|token| !token.is_empty())
+            {
+                self.expect_id = !self.expect_id && matches!(token[0], b'b' | b'B' | b'r' | b'R' | b's' | b'S');
+            }
+            return;
+        }
+
+        // Only lines with an o or h change are copied
+        let mut rewritten : Option<Vec<u8>> = None;
+        let mut copied = 0;
+        let mut start = 0;
+        while let Some(len) = self.line[start..].iter().position(|byte| !byte.is_ascii_whitespace())
+        {
+            start += len;
+            let end = self.line[start..].iter().position(u8::is_ascii_whitespace).map_or(self.line.len(), |len| start + len);
+            let token = &self.line[start..end];
+            match (self.in_comment, token)
+            {
+                // Comment text is kept as written
+                (true, b"$end") => self.in_comment = false,
+                (true, _) => (),
+                (false, b"$comment") => self.in_comment = true,
+                (false, _) if self.expect_id => self.expect_id = false,
+                (false, _) => match binary(token)
+                {
+                    Some(bits) =>
+                    {
+                        let output = rewritten.get_or_insert_with(|| Vec::with_capacity(self.line.len() * 4));
+                        output.extend(&self.line[copied..start]);
+                        output.push(b'b');
+                        output.extend(bits);
+                        copied = end;
+                        self.expect_id = true;
+                    },
+                    None => self.expect_id = matches!(token[0], b'b' | b'B' | b'r' | b'R' | b's' | b'S'),
+                },
+            }
+            start = end;
+        }
+        if let Some(mut output) = rewritten
+        {
+            output.extend(&self.line[copied..]);
+            self.line = output;
+        }
+    }
+}
+
+// Bits of an `o` or `h` value, `None` for any other token
+fn binary(token : &[u8]) -> Option<Vec<u8>>
+{
+    let (bits_per_digit, digits) = match token.split_first()
+    {
+        Some((b'o' | b'O', digits)) => (3, digits),
+        Some((b'h' | b'H', digits)) => (4, digits),
+        _ => return None,
+    };
+    if digits.is_empty()
+    {
+        return None;
+    }
+    let mut bits = Vec::with_capacity(digits.len() * bits_per_digit);
+    for digit in digits
+    {
+        let value = match digit
+        {
+            b'x' | b'X' | b'z' | b'Z' =>
+            {
+                bits.extend(std::iter::repeat_n(digit.to_ascii_lowercase(), bits_per_digit));
+                continue;
+            },
+            b'0'..=b'7' => digit - b'0',
+            b'8' | b'9' if bits_per_digit == 4 => digit - b'0',
+            b'a'..=b'f' if bits_per_digit == 4 => digit - b'a' + 10,
+            b'A'..=b'F' if bits_per_digit == 4 => digit - b'A' + 10,
+            _ => return None,
+        };
+        bits.extend((0..bits_per_digit).rev().map(|bit| b'0' + ((value >> bit) & 1)));
+    }
+    // Leading zeros are implied by VCD, dropping them keeps h0F short enough
+    // for a 5 bits signal. A zero before x or z is kept, it would be
+    // extended as x or z otherwise
+    let zeros = bits.iter().zip(&bits[1..]).take_while(|(bit, next)| **bit == b'0' && matches!(next, b'0' | b'1')).count();
+    bits.drain(..zeros);
+    Some(bits)
+}
+
+impl<R : BufRead> Read for RadixReader<R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize>
+    {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R : BufRead> BufRead for RadixReader<R>
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>
+    {
+        if self.pos >= self.line.len()
+        {
+            self.line.clear();
+            self.pos = 0;
+            self.inner.read_until(b'\n', &mut self.line)?;
+            self.rewrite_line();
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt : usize)
+    {
+        self.pos += amt;
+    }
+}
@@ -0,0 +1,101 @@
+//! Machine-readable summary of a merge, as written by `--stats`.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::{Result, SignalSets, SyncPoint, VCD, rounded_timeskews};
+
+/// Bumped whenever a field of [`MergeStats`] is renamed, removed or changes meaning
+pub const STATS_SCHEMA_VERSION : u32 = 1;
+
+/// Statistics about one input file
+#[derive(Debug, Serialize)]
+pub struct InputStats
+{
+    pub path : PathBuf,
+    pub size_bytes : u64,
+    pub signal_count : usize,
+    /// Set when the trace is synced on its reset signal
+    pub rst_end : Option<u64>,
+    /// Set when the trace is manually offset
+    pub offset : Option<i64>,
+    /// Shift applied to the trace during the merge
+    pub timeskew : u64,
+    /// Full paths of the signals no other input has
+    pub unique_signals : Vec<String>,
+}
+
+/// Statistics about a whole merge, timestamps are in units of `timescale`
+#[derive(Debug, Serialize)]
+pub struct MergeStats
+{
+    pub schema_version : u32,
+    pub timescale : String,
+    pub inputs : Vec<InputStats>,
+    /// Number of full paths every input has
+    pub common_signal_count : usize,
+    pub merged_signal_count : usize,
+    pub merged_timestamp_count : usize,
+    pub parse_seconds : f64,
+    pub merge_seconds : f64,
+    /// Not set by a dry run
+    pub write_seconds : Option<f64>,
+}
+
+impl MergeStats
+{
+    /// Collects the input statistics of normalized, not yet merged, traces,
+    /// to be merged with [`VCD::merge_rounded`] on `clock_period` (1 for
+    /// [`VCD::merge`])
+    pub fn new(vcds : &[VCD], clock_period : u64) -> Result<MergeStats>
+    {
+        let mut inputs = Vec::new();
+        let signal_sets = SignalSets::new(vcds);
+        for ((vcd, timeskew), unique_signals) in vcds.iter().zip(rounded_timeskews(vcds, clock_period)).zip(signal_sets.unique)
+        {
+            let (rst_end, offset) = match vcd.sync
+            {
+                SyncPoint::ResetSignal(rst_end) => (Some(rst_end), None),
+                SyncPoint::ManualOffset(offset) => (None, Some(offset)),
+                SyncPoint::Append => (None, None),
+            };
+            for source in &vcd.sources
+            {
+                inputs.push(InputStats {
+                    path : source.path.clone(),
+                    size_bytes : std::fs::metadata(&source.path)?.len(),
+                    signal_count : vcd.signals.len(),
+                    rst_end,
+                    offset,
+                    timeskew,
+                    unique_signals : unique_signals.clone(),
+                });
+            }
+        }
+
+        let timescale = vcds.first()
+            .map(|vcd| format!("{} {}", vcd.timescale_value, vcd.timescale_unit))
+            .unwrap_or_default();
+        Ok(MergeStats {
+            schema_version : STATS_SCHEMA_VERSION,
+            timescale,
+            inputs,
+            common_signal_count : signal_sets.common.len(),
+            merged_signal_count : 0,
+            merged_timestamp_count : 0,
+            parse_seconds : 0.0,
+            merge_seconds : 0.0,
+            write_seconds : None,
+        })
+    }
+
+    /// Writes the statistics as pretty-printed JSON
+    pub fn write(&self, path : &PathBuf) -> Result<()>
+    {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+//! Read-only well-formedness checks of a trace, as run by `--validate`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use vcd::Command::{ChangeScalar, ChangeVector, ChangeReal, ChangeString, Timestamp};
+use vcd::Parser;
+
+use crate::{IdCode, Result, VCD, VcdSyncError, WriteOptions, open_trace, signals};
+
+/// How bad an [`Issue`] is, errors are issues a merge would fail or go wrong on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity
+{
+    Warning,
+    Error,
+}
+
+/// One problem found in a trace
+#[derive(Clone, Debug)]
+pub struct Issue
+{
+    pub severity : Severity,
+    pub message : String,
+}
+
+/// Every problem found in a trace, in file order
+#[derive(Clone, Debug)]
+pub struct Report
+{
+    pub path : PathBuf,
+    pub issues : Vec<Issue>,
+}
+
+impl Report
+{
+    fn warning(&mut self, message : String)
+    {
+        self.issues.push(Issue { severity : Severity::Warning, message });
+    }
+
+    fn error(&mut self, message : String)
+    {
+        self.issues.push(Issue { severity : Severity::Error, message });
+    }
+
+    /// Worst severity of the issues, `None` for a clean trace
+    pub fn severity(&self) -> Option<Severity>
+    {
+        self.issues.iter().map(|issue| issue.severity).max()
+    }
+}
+
+/// Checks a trace without keeping any of its values.
+///
+/// Unreadable files are reported as an error issue, like any other problem.
+pub fn validate(file_path : &PathBuf) -> Report
+{
+    let mut report = Report { path : file_path.clone(), issues : Vec::new() };
+    let mut parser = match open_trace(file_path)
+    {
+        Ok(reader) => Parser::new(reader),
+        Err(error) =>
+        {
+            report.error(error.to_string());
+            return report;
+        },
+    };
+    let header = match parser.parse_header()
+    {
+        Ok(header) => header,
+        Err(error) =>
+        {
+            report.error(format!("Invalid header: {}", error));
+            return report;
+        },
+    };
+
+    if header.timescale.is_none()
+    {
+        report.error("No $timescale in the header".to_string());
+    }
+
+    // IdCode -> (declared width, full names)
+    let mut declared : HashMap<IdCode, (u32, Vec<String>)> = HashMap::new();
+    for (signal, code) in signals(&header.items)
+    {
+        if signal.scope.is_empty()
+        {
+            report.warning(format!("Variable {} is declared in no scope", signal.name));
+        }
+        declared.entry(code).or_insert((signal.width, Vec::new())).1.push(signal.full_name());
+    }
+    let mut aliases : Vec<_> = declared.iter().filter(|(_, (_, names))| names.len() > 1).collect();
+    aliases.sort_by_key(|(_, (_, names))| names[0].clone());
+    for (code, (_, names)) in aliases
+    {
+        report.warning(format!("IdCode {} is declared for several variables: {}", code, names.join(", ")));
+    }
+
+    // Each bad IdCode is only reported once, reversals are counted
+    let mut undeclared = HashSet::new();
+    let mut too_wide = HashSet::new();
+    let mut reversals = 0u64;
+    let mut first_reversal = None;
+    let mut repeats = 0u64;
+    let mut first_repeat = None;
+    let mut current_timestamp = 0;
+    let mut timestamp_seen = false;
+    for cmd in parser
+    {
+        let cmd = match cmd
+        {
+            Ok(cmd) => cmd,
+            Err(error) =>
+            {
+                report.error(format!("Invalid value change after #{}: {}", current_timestamp, error));
+                break;
+            },
+        };
+        let (id, width) = match cmd
+        {
+            ChangeScalar(id, _) | ChangeReal(id, _) | ChangeString(id, _) => (id, None),
+            ChangeVector(id, vector) => (id, Some(vector.len() as u32)),
+            Timestamp(timestamp) =>
+            {
+                if timestamp < current_timestamp
+                {
+                    reversals += 1;
+                    first_reversal.get_or_insert((current_timestamp, timestamp));
+                }
+                if timestamp_seen && timestamp == current_timestamp
+                {
+                    repeats += 1;
+                    first_repeat.get_or_insert(timestamp);
+                }
+                timestamp_seen = true;
+                current_timestamp = timestamp;
+                continue;
+            },
+            _ => continue,
+        };
+        match (declared.get(&id), width)
+        {
+            (None, _) if undeclared.insert(id) =>
+                report.error(format!("IdCode {} changes at #{} but isn't declared in the header", id, current_timestamp)),
+            (Some((declared_width, names)), Some(width)) if width > *declared_width && too_wide.insert(id) =>
+                report.error(format!("{} is declared {} bits wide but gets a {} bits value at #{}",
+                                     names[0], declared_width, width, current_timestamp)),
+            _ => (),
+        }
+    }
+
+    if let Some((prev, next)) = first_reversal
+    {
+        report.error(format!("{} timestamp reversals, the first one is #{} after #{}", reversals, next, prev));
+    }
+    if let Some(timestamp) = first_repeat
+    {
+        report.warning(format!("{} repeated timestamps, the first one is #{}", repeats, timestamp));
+    }
+    report
+}
+
+/// What a merged trace written with [`crate::write_vcd_to_file`] should read back as,
+/// as checked by `--verify-merge`
+#[derive(Clone, Debug)]
+pub struct MergeCheck
+{
+    pub signal_count : usize,
+    /// `None` when there is no value change to write
+    pub last_timestamp : Option<u64>,
+}
+
+impl MergeCheck
+{
+    /// Expected output of `merged`, to be taken before it is written
+    pub fn new(merged : &VCD, options : &WriteOptions) -> MergeCheck
+    {
+        let start = options.trim_start.unwrap_or(0);
+        let end = options.trim_end.unwrap_or(u64::MAX);
+        // A trimmed output starts with a snapshot at `start`
+        let last_timestamp = match start <= end
+        {
+            true => merged.values.range(..=end).next_back().map(|(timestamp, _)| (*timestamp).max(start)),
+            false => None,
+        };
+        MergeCheck { signal_count : merged.signals.len(), last_timestamp }
+    }
+
+    /// Parses `output_file` back and checks it against the expected trace
+    pub fn check(&self, output_file : &Path) -> Result<()>
+    {
+        let failed = |reason : String| VcdSyncError::VerifyFailed { file : output_file.to_path_buf(), reason };
+        let mut parser = Parser::new(open_trace(&output_file.to_path_buf())?);
+        let header = parser.parse_header()?;
+        let vars = signals(&header.items);
+        let signal_count = vars.len();
+        let declared : HashSet<IdCode> = vars.into_iter().map(|(_, code)| code).collect();
+        if signal_count != self.signal_count
+        {
+            return Err(failed(format!("{} variables declared, expected {}", signal_count, self.signal_count)));
+        }
+
+        let mut last_timestamp = None;
+        for cmd in parser
+        {
+            match cmd?
+            {
+                Timestamp(timestamp) => last_timestamp = Some(timestamp),
+                ChangeScalar(id, _) | ChangeVector(id, _) | ChangeReal(id, _) | ChangeString(id, _) if !declared.contains(&id) =>
+                    return Err(failed(format!("IdCode {} changes but isn't declared in the header", id))),
+                _ => (),
+            }
+        }
+        if last_timestamp != self.last_timestamp
+        {
+            let show = |timestamp : Option<u64>| timestamp.map_or("none".to_string(), |timestamp| format!("#{}", timestamp));
+            return Err(failed(format!("last timestamp is {}, expected {}", show(last_timestamp), show(self.last_timestamp))));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Traces built in memory are synced and merged like parsed ones.
+
+mod common;
+
+use vcd_sync::{Glitch, InitStrategy, ResetPolarity, StoredValue, SyncPoint, TimescaleNormalizer, TimescaleUnit, Value, VarType, VcdBuilder, Vector};
+
+fn trace(name : &str, rst_end : u64) -> VcdBuilder
+{
+    let mut builder = common::reset_trace(name, "top", rst_end);
+    builder.add_signal("top.valid", 1, VarType::Wire);
+    builder.add_event(rst_end + 5, "top.valid", StoredValue::Scalar(Value::V1)).unwrap();
+    builder
+}
+
+#[test]
+fn built_traces_merge_on_their_reset()
+{
+    let mut merged = trace("a", 10).build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+    let b = trace("b", 40).build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+    assert_eq!(merged.sync, SyncPoint::ResetSignal(10));
+    merged.merge(b).unwrap();
+
+    assert_eq!(merged.sync, SyncPoint::ResetSignal(40));
+    // a is shifted by 30, so both valid signals rise at 45
+    let changes : Vec<_> = merged.changes().filter(|(timestamp, _, _)| *timestamp == 45).map(|(_, name, _)| name).collect();
+    assert_eq!(changes, ["valid", "valid"]);
+}
+
+#[test]
+fn unknown_signals_are_rejected()
+{
+    let mut builder = trace("a", 10);
+    assert!(builder.add_event(0, "top.nope", StoredValue::Scalar(Value::V0)).is_err());
+    assert!(builder.build("top.nope", ResetPolarity::ActiveLow).is_err());
+}
+
+#[test]
+fn merges_can_start_unknown()
+{
+    let mut merged = trace("a", 10).build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+    merged.merge(trace("b", 40).build("top.reset_n", ResetPolarity::ActiveLow).unwrap()).unwrap();
+    merged.initialize_to(Value::X);
+
+    let init : Vec<_> = merged.changes().filter(|(timestamp, _, _)| *timestamp == 0).map(|(_, _, value)| value.to_string()).collect();
+    assert_eq!(init, ["x"; 4]);
+}
+
+#[test]
+fn merges_can_start_by_var_type()
+{
+    let mut a = trace("a", 10);
+    a.add_signal("top.state", 2, VarType::Reg).add_signal("top.bus", 1, VarType::Tri);
+    let mut merged = a.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+    merged.merge(trace("b", 40).build("top.reset_n", ResetPolarity::ActiveLow).unwrap()).unwrap();
+    merged.initialize_with(|var_type| InitStrategy::ByVarType.value(var_type));
+
+    let init : Vec<_> = merged.changes().filter(|(timestamp, _, _)| *timestamp == 0).map(|(_, name, value)| format!("{} {}", name, value)).collect();
+    assert_eq!(init, ["reset_n 0", "valid 0", "state xx", "bus z", "reset_n 0", "valid 0"]);
+}
+
+#[test]
+fn display_lists_the_first_signals()
+{
+    let mut builder = VcdBuilder::new("wide", 10, TimescaleUnit::PS);
+    builder.add_signal("top.reset_n", 1, VarType::Wire);
+    for i in 0..24
+    {
+        builder.add_signal(&format!("top.bus{}", i), 32, VarType::Reg);
+    }
+    builder.add_event(0, "top.reset_n", StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(123_456, "top.reset_n", StoredValue::Scalar(Value::V1)).unwrap();
+    let summary = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap().to_string();
+
+    let lines : Vec<&str> = summary.lines().collect();
+    assert_eq!(lines[0], "VCD: 25 signals, 10 ps timescale, T=0..1_234_560 ps");
+    assert_eq!(lines[1], "  top.reset_n  [wire, 1b]  2 changes");
+    assert_eq!(lines.len(), 22);
+    assert_eq!(lines[21], "  ... and 5 more");
+}
+
+#[test]
+fn first_unknown_bits_are_found()
+{
+    let mut builder = trace("a", 10);
+    builder.add_signal("top.data", 4, VarType::Reg);
+    builder.add_event(20, "top.data", StoredValue::Vector("10x1".parse().unwrap())).unwrap();
+    builder.add_event(30, "top.valid", StoredValue::Scalar(Value::X)).unwrap();
+    builder.add_event(40, "top.data", StoredValue::Vector("xxxx".parse().unwrap())).unwrap();
+    let vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    assert_eq!(vcd.first_timestamps_with(Value::X), [(1, 30), (2, 20)]);
+    assert!(vcd.first_timestamps_with(Value::Z).is_empty());
+}
+
+#[test]
+fn forced_coarser_timescale_truncates()
+{
+    let mut builder = VcdBuilder::new("ps", 1, TimescaleUnit::PS);
+    builder.add_signal("top.reset_n", 1, VarType::Wire).add_signal("top.valid", 1, VarType::Wire);
+    builder.add_event(0, "top.reset_n", StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(2000, "top.reset_n", StoredValue::Scalar(Value::V1)).unwrap();
+    builder.add_event(3400, "top.valid", StoredValue::Scalar(Value::V1)).unwrap();
+    builder.add_event(3900, "top.valid", StoredValue::Scalar(Value::V0)).unwrap();
+    let mut vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    TimescaleNormalizer::forced(1, TimescaleUnit::NS).unwrap().normalize(&mut vcd).unwrap();
+    assert_eq!((vcd.timescale_value, vcd.timescale_unit), (1, TimescaleUnit::NS));
+    assert_eq!(vcd.sync, SyncPoint::ResetSignal(2));
+    // Both changes of valid land on 3 ns, in order
+    let changes : Vec<_> = vcd.changes().map(|(timestamp, name, value)| format!("#{} {} {}", timestamp, name, value)).collect();
+    assert_eq!(changes, ["#0 reset_n 0", "#2 reset_n 1", "#3 valid 1", "#3 valid 0"]);
+}
+
+#[test]
+fn shared_and_unique_signals_are_listed()
+{
+    let a = trace("a", 10).build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+    let mut b = trace("b", 40);
+    b.add_signal("top.ready", 1, VarType::Wire);
+    let b = b.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    assert_eq!(a.common_signals(&b).collect::<Vec<_>>(), ["top.reset_n", "top.valid"]);
+    assert!(a.unique_to_self(&b).next().is_none());
+    assert_eq!(a.unique_to_other(&b).collect::<Vec<_>>(), ["top.ready"]);
+}
+
+#[test]
+fn clock_is_the_busiest_bit()
+{
+    let mut builder = trace("a", 10);
+    builder.add_signal("top.clk", 1, VarType::Wire).add_signal("top.count", 8, VarType::Reg);
+    for timestamp in 0..10
+    {
+        let bit = if timestamp % 2 == 0 { Value::V0 } else { Value::V1 };
+        builder.add_event(timestamp * 5, "top.clk", StoredValue::Scalar(bit)).unwrap();
+        builder.add_event(timestamp * 5, "top.count", StoredValue::Vector(Vector::from(vec![bit; 8]))).unwrap();
+    }
+    let vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    // Vectors changing as often are never clocks
+    assert_eq!(vcd.infer_clock().map(|signal| signal.full_name()).as_deref(), Some("top.clk"));
+    assert_eq!(vcd.clock_period("top.clk"), Some(10));
+}
+
+#[test]
+fn declared_only_signals_are_inactive()
+{
+    let mut builder = trace("a", 10);
+    builder.add_signal("top.ghost", 1, VarType::Wire);
+    let vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    assert_eq!(vcd.active_signal_count(), 2);
+    assert_eq!(vcd.inactive_signals().into_iter().collect::<Vec<_>>(), [2]);
+}
+
+#[test]
+fn resampling_keeps_the_state_at_each_period()
+{
+    // reset_n rises at 10, valid at 15
+    let mut builder = trace("a", 10);
+    builder.add_event(17, "top.valid", StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(18, "top.valid", StoredValue::Scalar(Value::V1)).unwrap();
+    builder.add_event(31, "top.valid", StoredValue::Scalar(Value::V0)).unwrap();
+    let vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    let resampled = vcd.resample(10);
+    let changes : Vec<_> = resampled.changes().map(|(timestamp, name, value)| format!("#{} {} {}", timestamp, name, value)).collect();
+    // The glitch of valid between 10 and 20 is lost
+    assert_eq!(changes, ["#0 reset_n 0", "#10 reset_n 1", "#20 valid 1", "#40 valid 0"]);
+    assert_eq!(resampled.signals, vcd.signals);
+}
+
+#[test]
+fn short_pulses_are_glitches()
+{
+    // reset_n rises at 10, valid at 15
+    let mut builder = trace("a", 10);
+    builder.add_event(17, "top.valid", StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(18, "top.valid", StoredValue::Scalar(Value::V1)).unwrap();
+    builder.add_event(30, "top.valid", StoredValue::Scalar(Value::V0)).unwrap();
+    let mut vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    assert_eq!(vcd.glitches(2), [Glitch { signal : 1, timestamp : 17, width : 1 }]);
+    assert!(vcd.glitches(1).is_empty());
+    assert_eq!(vcd.filter_glitches(2, Some(0)), 1);
+    let changes : Vec<_> = vcd.changes().map(|(timestamp, name, value)| format!("#{} {} {}", timestamp, name, value)).collect();
+    assert_eq!(changes, ["#0 reset_n 0", "#10 reset_n 1", "#15 valid 1", "#30 valid 0"]);
+}
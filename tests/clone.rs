@@ -0,0 +1,23 @@
+//! Cloned traces compare equal to the original, until one of them is merged.
+
+mod common;
+
+use common::TRACE;
+use vcd_sync::{SyncPoint, VCD};
+
+#[test]
+fn clone_roundtrip()
+{
+    let original = VCD::from_str(TRACE, "top.reset").unwrap();
+    let mut other = original.clone();
+    other.sync = SyncPoint::ManualOffset(0);
+
+    let cloned = original.clone();
+    assert_eq!(original, cloned);
+
+    let mut merged = cloned.clone();
+    merged.merge(original.clone()).unwrap();
+    assert_ne!(merged, cloned);
+    // Same trace, different sync point
+    assert_ne!(original, other);
+}
@@ -0,0 +1,37 @@
+//! Fixtures shared by the test crates, each one only uses some of them.
+
+#![allow(dead_code)]
+
+use vcd_sync::{StoredValue, TimescaleUnit, Value, VarType, VcdBuilder};
+
+/// A 1 ns trace released from `top.reset` at 10, with a bus and a real
+/// changing before and after it
+pub const TRACE : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 4 \" state $end
+$var real 64 # level $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0000 \"
+r0.5 #
+#10
+1!
+#20
+b1010 \"
+r1.25 #
+";
+
+/// A 1 ns trace built in memory, held in reset by `{scope}.reset_n` from 0
+/// to `rst_end`
+pub fn reset_trace(name : &str, scope : &str, rst_end : u64) -> VcdBuilder
+{
+    let reset = format!("{}.reset_n", scope);
+    let mut builder = VcdBuilder::new(name, 1, TimescaleUnit::NS);
+    builder.add_signal(&reset, 1, VarType::Wire);
+    builder.add_event(0, &reset, StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(rst_end, &reset, StoredValue::Scalar(Value::V1)).unwrap();
+    builder
+}
@@ -0,0 +1,128 @@
+//! Differences of a capture against a reference, written as a VCD.
+
+use std::fs;
+
+use vcd_sync::{ParseOptions, VCD, VcdSyncError};
+use vcd_sync::diff::{diff, write_vcd_diff};
+
+const REFERENCE : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var reg 4 \" data $end
+$var wire 1 # ref_only $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0 \"
+0#
+#10
+1!
+#20
+b101 \"
+1#
+#30
+b110 \"
+";
+
+// Reset released 5 ns later, data only differs at 25 ns once aligned
+const ACTUAL : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var reg 4 \" data $end
+$var wire 1 # dut_only $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0 \"
+0#
+#15
+1!
+#25
+b100 \"
+#35
+b110 \"
+1#
+";
+
+#[test]
+fn only_divergent_changes_are_written()
+{
+    let dir = std::env::temp_dir().join(format!("vcd_sync_diff_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let reference = VCD::from_str(REFERENCE, "top.reset").unwrap();
+    let actual = VCD::from_str(ACTUAL, "top.reset").unwrap();
+
+    let read = |include_reference_only|
+    {
+        let output_file = dir.join("diff.vcd");
+        write_vcd_diff(&reference, &actual, &output_file, include_reference_only).unwrap();
+        VCD::with_offset(&output_file, 0, &ParseOptions::default()).unwrap()
+    };
+    let diff = read(false);
+    let with_reference = read(true);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let names : Vec<String> = diff.signals.iter().map(|signal| signal.full_name()).collect();
+    assert_eq!(names, ["top.reset", "top.data", "top.dut_only"]);
+    let changes : Vec<String> = diff.changes()
+        .map(|(timestamp, name, value)| format!("#{} {} {}", timestamp, name, value))
+        .collect();
+    // Common signals without a difference are never written, they read as x
+    assert_eq!(changes, ["#0 reset x", "#0 data xxxx", "#0 dut_only 0", "#25 data 100", "#35 dut_only 1"]);
+
+    assert!(with_reference.signals.iter().any(|signal| signal.full_name() == "top.ref_only"));
+}
+
+// A trace whose sig holds `sig` from #0 on and never changes
+fn constant(sig : char) -> VCD
+{
+    VCD::from_str(&format!("$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 1 \" sig $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+{}\"
+#10
+1!
+", sig), "top.reset").unwrap()
+}
+
+#[test]
+fn differences_from_the_start_are_reported()
+{
+    let differences = diff(&constant('1'), &constant('0'), &[]).unwrap();
+    let found : Vec<_> = differences.iter()
+        .map(|difference| format!("#{} {} {:?} {:?}", difference.timestamp, difference.signal, difference.left, difference.right))
+        .collect();
+    assert_eq!(found, ["#0 top.sig Some(\"1\") Some(\"0\")"]);
+    assert!(diff(&constant('1'), &constant('1'), &[]).unwrap().is_empty());
+}
+
+#[test]
+fn differences_from_the_start_are_written()
+{
+    let dir = std::env::temp_dir().join(format!("vcd_sync_diff_start_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output_file = dir.join("diff.vcd");
+    write_vcd_diff(&constant('1'), &constant('0'), &output_file, false).unwrap();
+    let written = VCD::with_offset(&output_file, 0, &ParseOptions::default()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let changes : Vec<String> = written.changes()
+        .map(|(timestamp, name, value)| format!("#{} {} {}", timestamp, name, value))
+        .collect();
+    assert_eq!(changes, ["#0 reset x", "#0 sig 0"]);
+}
+
+#[test]
+fn unknown_signals_are_errors()
+{
+    let error = diff(&constant('1'), &constant('1'), &["top.sig".to_string(), "top.sgi".to_string()]).unwrap_err();
+    assert!(matches!(&error, VcdSyncError::UnknownSignal { signal } if signal == "top.sgi"), "{}", error);
+    assert!(diff(&constant('1'), &constant('1'), &["top.sig".to_string()]).unwrap().is_empty());
+}
@@ -0,0 +1,176 @@
+//! Merges of the traces of this directory, checked byte for byte against
+//! golden outputs. Build with the `regenerate-golden` feature to overwrite
+//! the golden files with the current outputs instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vcd_sync::{ParseOptions, SignalFilter, SyncPoint, VCD, VcdSyncError, WriteOptions, merge_all, write_vcd_to_file};
+
+fn fixture(name : &str) -> PathBuf
+{
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/integration").join(name)
+}
+
+// Merges `inputs` on their top.reset_n and compares the output to `golden`
+fn check_merge(inputs : &[&str], golden : &str)
+{
+    let inputs : Vec<PathBuf> = inputs.iter().map(|input| fixture(input)).collect();
+    let merged = merge_all(&inputs, "top.reset_n").unwrap();
+
+    let output = std::env::temp_dir().join(format!("vcd_sync_{}_{}", std::process::id(), golden));
+    write_vcd_to_file(merged, &output, &WriteOptions::default()).unwrap();
+    let written = fs::read(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+
+    if cfg!(feature = "regenerate-golden")
+    {
+        fs::write(fixture(golden), &written).unwrap();
+        return;
+    }
+    let expected = fs::read(fixture(golden)).unwrap();
+    assert!(written == expected, "{} changed, got:\n{}", golden, String::from_utf8_lossy(&written));
+}
+
+#[test]
+fn merge_with_skew_matches_golden()
+{
+    // b is released 50 ns after a
+    check_merge(&["a.vcd", "b.vcd"], "ab_merged.vcd");
+}
+
+#[test]
+fn octal_and_hex_changes_are_read_as_binary()
+{
+    // Both are released at 10 ns, the o and h changes of radix are written in binary
+    check_merge(&["a.vcd", "radix.vcd"], "radix_merged.vcd");
+}
+
+#[test]
+fn reset_fallbacks_are_tried_in_order()
+{
+    let options = ParseOptions { reset_fallbacks : vec!["top.rst".to_string(), "top.reset_n".to_string()], ..ParseOptions::default() };
+    let vcd = VCD::new(&fixture("b.vcd"), "top.reset", &options).unwrap();
+    assert_eq!(vcd.sync, SyncPoint::ResetSignal(60));
+
+    let options = ParseOptions { reset_fallbacks : vec!["top.rst".to_string()], ..ParseOptions::default() };
+    let error = VCD::new(&fixture("b.vcd"), "top.reset", &options).unwrap_err();
+    assert!(error.to_string().starts_with("None of the reset signals top.reset, top.rst is found in"), "{}", error);
+}
+
+#[test]
+fn traces_without_changes_are_empty()
+{
+    let header = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$upscope $end
+$enddefinitions $end
+";
+    let error = VCD::from_str(header, "top.reset").unwrap_err();
+    assert!(matches!(error, VcdSyncError::EmptyFile { .. }), "{}", error);
+    assert!(matches!(VCD::from_str(&format!("{}#0\n#10\n", header), "top.reset"), Err(VcdSyncError::EmptyFile { .. })));
+}
+
+#[test]
+fn filtering_every_signal_out_is_not_an_empty_file()
+{
+    let options = ParseOptions {
+        filter : SignalFilter { exclude_scopes : vec!["top".to_string()], ..SignalFilter::default() },
+        ..ParseOptions::default()
+    };
+    let error = VCD::appended(&fixture("a.vcd"), &options).unwrap_err();
+    assert!(matches!(error, VcdSyncError::NoSignalSelected { .. }), "{}", error);
+}
+
+#[test]
+fn prefixes_are_nested_in_the_output_module()
+{
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["a.vcd", "b.vcd", "--reset-signal", "top.reset_n", "--output-file", "-", "--no-provenance",
+               "--prefix", "a.vcd=board_a", "--prefix", "b.vcd=board_b"])
+        .current_dir(fixture(""))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let scopes : Vec<_> = stdout.lines().take_while(|line| *line != "$enddefinitions $end")
+        .filter(|line| line.starts_with("$scope") || line.starts_with("$upscope"))
+        .collect();
+    assert_eq!(scopes, ["$scope module top $end", "$scope module board_a $end", "$upscope $end",
+                        "$scope module board_b $end", "$upscope $end", "$upscope $end"]);
+}
+
+#[test]
+fn offsets_without_files_are_rejected()
+{
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["--offset", "5", "--output-file", "-"])
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Expected 0 offsets"), "{}", stderr);
+}
+
+#[test]
+fn inferred_clocks_drive_clock_alignment()
+{
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["a.vcd", "b.vcd", "--reset-signal", "top.reset_n", "--output-file", "-", "--infer-clock", "--clock-align"])
+        .current_dir(fixture(""))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("on the rising edges of top.clk"), "{}", stderr);
+}
+
+#[test]
+fn verify_merge_is_checked_before_parsing()
+{
+    // The inputs don't exist, they would fail to parse
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["missing_a.vcd", "missing_b.vcd", "--reset-signal", "top.reset_n", "--output-file", "-", "--verify-merge"])
+        .current_dir(fixture(""))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--verify-merge only checks VCD output files"), "{}", stderr);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["a.vcd", "b.vcd", "--reset-signal", "top.reset_n", "--split-output", "out", "--verify-merge"])
+        .current_dir(fixture(""))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn appended_traces_only_merge_with_appended_ones()
+{
+    let mut appended = VCD::appended(&fixture("a.vcd"), &ParseOptions::default()).unwrap();
+    let synced = VCD::new(&fixture("b.vcd"), "top.reset_n", &ParseOptions::default()).unwrap();
+    let error = appended.merge(synced).unwrap_err();
+    assert!(matches!(error, VcdSyncError::SyncMismatch { first : SyncPoint::Append, second : SyncPoint::ResetSignal(_) }), "{}", error);
+    assert_eq!(error.to_string(), "Can't merge traces placed differently in time: appended and reset end at 60");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn cache_keys_only_follow_the_settings()
+{
+    use vcd_sync::cache::cache_path;
+
+    let dir = Path::new("cache");
+    let options = ParseOptions::default();
+    let path = cache_path(dir, &fixture("a.vcd"), &("top.reset_n", &options)).unwrap();
+    assert_eq!(path, cache_path(dir, &fixture("a.vcd"), &("top.reset_n", &options.clone())).unwrap());
+    assert_eq!(path.file_stem().unwrap().len(), 32);
+
+    let filtered = ParseOptions { filter : SignalFilter { exclude_scopes : vec!["top".to_string()], ..Default::default() }, ..Default::default() };
+    assert_ne!(path, cache_path(dir, &fixture("a.vcd"), &("top.reset_n", &filtered)).unwrap());
+    assert_ne!(path, cache_path(dir, &fixture("b.vcd"), &("top.reset_n", &options)).unwrap());
+}
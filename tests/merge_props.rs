@@ -0,0 +1,99 @@
+//! Merging a trace with an unskewed copy of itself only duplicates it.
+
+mod common;
+
+use std::collections::HashSet;
+
+use proptest::prelude::*;
+use vcd_sync::{NameConflict, ResetPolarity, StoredValue, Value, VarType, Vector, VCD};
+
+const RESET : &str = "top.reset_n";
+
+// Bit strings of `width` bits, a scalar for 1 bit signals
+fn value(width : u32) -> impl Strategy<Value = StoredValue>
+{
+    let bit = prop_oneof![Just(Value::V0), Just(Value::V1), Just(Value::X), Just(Value::Z)];
+    prop::collection::vec(bit, width as usize).prop_map(move |bits| match width
+    {
+        1 => StoredValue::Scalar(bits[0]),
+        _ => StoredValue::Vector(bits.into_iter().collect::<Vector>()),
+    })
+}
+
+// Signals of top with their width, the reset release, then (timestamp,
+// signal index, value) events
+type Trace = (Vec<(String, u32)>, u64, Vec<(u64, usize, StoredValue)>);
+
+fn trace() -> impl Strategy<Value = Trace>
+{
+    let signals = prop::collection::hash_set("[a-z][a-z0-9_]{0,6}".prop_filter("reset name", |name| name != "reset_n"), 1..8)
+        .prop_flat_map(|names|
+        {
+            let names : Vec<String> = names.into_iter().collect();
+            let widths = prop::collection::vec(1..=16u32, names.len());
+            (Just(names), widths).prop_map(|(names, widths)| names.into_iter().zip(widths).collect::<Vec<_>>())
+        });
+    (signals, 1..100u64).prop_flat_map(|(signals, rst_end)|
+    {
+        let widths : Vec<u32> = signals.iter().map(|(_, width)| *width).collect();
+        let event = (0..200u64, 0..widths.len()).prop_flat_map(move |(timestamp, index)|
+            (Just(timestamp), Just(index), value(widths[index])));
+        (Just(signals), Just(rst_end), prop::collection::vec(event, 0..50))
+    })
+}
+
+fn build(signals : &[(String, u32)], rst_end : u64, events : &[(u64, usize, StoredValue)]) -> VCD
+{
+    let mut builder = common::reset_trace("trace", "top", rst_end);
+    for (name, width) in signals
+    {
+        builder.add_signal(&format!("top.{}", name), *width, VarType::Wire);
+    }
+    for (timestamp, index, value) in events
+    {
+        builder.add_event(*timestamp, &format!("top.{}", signals[*index].0), value.clone()).unwrap();
+    }
+    builder.build(RESET, ResetPolarity::ActiveLow).unwrap()
+}
+
+// Changes of one signal after timestamp 0, which a merge initializes
+fn changes(vcd : &VCD, id : u32) -> Vec<(u64, StoredValue)>
+{
+    vcd.values.range(1..)
+        .flat_map(|(timestamp, changes)| changes.iter().filter(|(changed, _)| *changed == id).map(|(_, value)| (*timestamp, value.clone())))
+        .collect()
+}
+
+proptest!
+{
+    #[test]
+    fn merge_with_itself_duplicates_every_signal((signals, rst_end, events) in trace())
+    {
+        let vcd = build(&signals, rst_end, &events);
+        let mut merged = vcd.clone();
+        merged.merge(vcd.clone()).unwrap();
+
+        let count = vcd.signals.len() as u32;
+        prop_assert_eq!(merged.signals.len() as u32, 2 * count);
+        let mut timestamps : Vec<u64> = vcd.values.keys().copied().collect();
+        if timestamps.first() != Some(&0)
+        {
+            timestamps.insert(0, 0);
+        }
+        prop_assert_eq!(merged.values.keys().copied().collect::<Vec<_>>(), timestamps);
+        for id in 0..count
+        {
+            prop_assert_eq!(changes(&merged, id), changes(&vcd, id));
+            prop_assert_eq!(changes(&merged, id + count), changes(&vcd, id));
+        }
+
+        // The copies are renamed, the originals keep their name
+        merged.resolve_name_conflicts(NameConflict::SuffixIndex);
+        let names : Vec<String> = merged.signals.iter().map(|signal| signal.full_name()).collect();
+        prop_assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+        for (signal, name) in vcd.signals.iter().zip(&names)
+        {
+            prop_assert_eq!(&signal.full_name(), name);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Scope hierarchy of the written VCD, read back with the vcd crate.
+
+mod common;
+
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+use vcd::ScopeItem;
+use vcd_sync::{NameConflict, ParseOptions, ResetPolarity, VarType, VCD, WriteOptions, write_vcd_to_file};
+
+const NESTED : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$scope module soc $end
+$scope module cpu $end
+$scope module alu $end
+$var wire 4 \" op $end
+$upscope $end
+$var wire 1 # valid $end
+$upscope $end
+$scope module uart $end
+$var wire 1 $ tx $end
+$upscope $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0000 \"
+0#
+1$
+#10
+1!
+#20
+b0101 \"
+1#
+";
+
+// Deepest scope nesting of a header
+fn depth(items : &[ScopeItem]) -> usize
+{
+    items.iter().map(|item| match item
+    {
+        ScopeItem::Scope(scope) => 1 + depth(&scope.items),
+        _ => 0,
+    }).max().unwrap_or(0)
+}
+
+fn header_depth(path : &Path) -> usize
+{
+    let mut parser = vcd::Parser::new(BufReader::new(fs::File::open(path).unwrap()));
+    depth(&parser.parse_header().unwrap().items)
+}
+
+#[test]
+fn written_scopes_match_the_input_depth()
+{
+    let dir = std::env::temp_dir().join(format!("vcd_sync_scopes_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("nested.vcd");
+    let output = dir.join("out.vcd");
+    fs::write(&input, NESTED).unwrap();
+
+    let vcd = VCD::new(&input, "top.reset", &ParseOptions::default()).unwrap();
+    write_vcd_to_file(vcd, &output, &WriteOptions::default()).unwrap();
+    let (input_depth, output_depth) = (header_depth(&input), header_depth(&output));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(input_depth, 4);
+    assert_eq!(output_depth, input_depth);
+}
+
+fn dut(name : &str, scope : &str) -> VCD
+{
+    let mut builder = common::reset_trace(name, scope, 10);
+    builder.add_signal(&format!("{}.cpu.valid", scope), 1, VarType::Wire);
+    builder.build(&format!("{}.reset_n", scope), ResetPolarity::ActiveLow).unwrap()
+}
+
+#[test]
+fn renamed_scopes_are_deduplicated()
+{
+    let mut merged = dut("a", "dut_a");
+    merged.merge(dut("b", "dut_b")).unwrap();
+    let renames = [("dut_a", "dut"), ("dut_b", "dut"), ("nope", "dut")].map(|(old, new)| (old.to_string(), new.to_string()));
+    let unmatched = merged.resolve_renamed_conflicts(NameConflict::SuffixIndex, &renames, "top");
+
+    assert_eq!(unmatched, ["nope"]);
+    let names : Vec<_> = merged.signals.iter().map(|signal| signal.full_name()).collect();
+    assert_eq!(names, ["dut.reset_n", "dut.cpu.valid", "dut.reset_n_2", "dut.cpu.valid_2"]);
+}
+
+#[test]
+fn unmatched_upscopes_are_ignored()
+{
+    let vcd = VCD::from_str("$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$upscope $end
+$comment
+    $scope and $upscope are only words here
+$end
+$upscope
+$end
+$scope module other $end $var wire 1 \" data $end $upscope $end $upscope $end
+$enddefinitions $end
+#0
+0!
+#10
+1!
+", "top.reset").unwrap();
+    let names : Vec<_> = vcd.signals.iter().map(|signal| signal.full_name()).collect();
+    assert_eq!(names, ["top.reset", "other.data"]);
+}
@@ -0,0 +1,114 @@
+//! Merged output written to stdout with `--output-file -`.
+
+mod common;
+
+use std::fs;
+use std::process::{Command, Output};
+
+use common::TRACE;
+
+const TRACE_B : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 1 \" valid $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+#30
+1!
+#40
+1\"
+";
+
+// b is 20 ns late, so a is shifted forward to match it, every signal
+// starts at 0 in the initial dump
+const MERGED : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 4 \" state $end
+$var real 64 # level $end
+$var wire 1 $ reset_2 $end
+$var wire 1 % valid $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+b0000 \"
+r0 #
+0$
+0%
+$end
+#20
+0!
+b0000 \"
+r0.5 #
+#30
+1!
+1$
+#40
+b1010 \"
+r1.25 #
+1%
+";
+
+// Merges a and b to stdout with these extra arguments
+fn merge(name : &str, args : &[&str]) -> Output
+{
+    let dir = std::env::temp_dir().join(format!("vcd_sync_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.vcd"), TRACE).unwrap();
+    fs::write(dir.join("b.vcd"), TRACE_B).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["a.vcd", "b.vcd", "--reset-signal", "top.reset", "--output-file", "-", "--no-provenance"])
+        .args(args)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+#[test]
+fn merged_trace_is_written_to_stdout()
+{
+    let output = merge("stdout", &[]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), MERGED);
+}
+
+#[test]
+fn streamed_merge_is_identical()
+{
+    // A 1 byte budget is exceeded by any input
+    let output = merge("stream", &["--max-memory", "0.000001"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("streaming the last file"));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), MERGED);
+}
+
+#[test]
+fn kept_scopes_are_nested_per_file()
+{
+    let dir = std::env::temp_dir().join(format!("vcd_sync_kept_scopes_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let capture = TRACE_B.replace("module top", "module cpu").replace("valid", "clk");
+    fs::write(dir.join("capture1.vcd"), &capture).unwrap();
+    fs::write(dir.join("capture2.vcd"), &capture).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vcd_sync"))
+        .args(["capture1.vcd", "capture2.vcd", "--reset-signal", "cpu.reset", "--output-file", "-", "--no-provenance", "--keep-scope-structure",
+               "--prefix", "capture2.vcd=board_b"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let merged = vcd_sync::VCD::from_str(&String::from_utf8(output.stdout).unwrap(), "top.capture1.cpu.reset").unwrap();
+    let names : Vec<_> = merged.signals.iter().map(|signal| signal.full_name()).collect();
+    assert_eq!(names, ["top.capture1.cpu.reset", "top.capture1.cpu.clk", "top.board_b.cpu.reset", "top.board_b.cpu.clk"]);
+}
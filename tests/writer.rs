@@ -0,0 +1,316 @@
+//! VCD output written to an in-memory buffer.
+
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use common::TRACE;
+
+use vcd_sync::{ResetPolarity, StoredValue, TimescaleUnit, TimestampValues, Value, VarType, VCD, VcdBuilder, WriteOptions,
+               merge_zero_duration_timestamps, prune_empty_timestamps, write_csv, write_fst, write_vcd_to_writer};
+
+const STRINGS : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var string 0 \" label $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+#10
+1!
+sidle \"
+#20
+sbusy \"
+";
+
+#[test]
+fn write_to_cursor()
+{
+    let vcd = VCD::from_str(TRACE, "top.reset").unwrap();
+
+    let mut output = Cursor::new(Vec::new());
+    write_vcd_to_writer(vcd, &mut output, &WriteOptions::default()).unwrap();
+    let output = String::from_utf8(output.into_inner()).unwrap();
+    assert!(output.contains("$timescale 1 ns $end"), "{}", output);
+    assert!(output.contains("#10\n1!\n"), "{}", output);
+}
+
+#[test]
+fn provenance_timeskews_are_in_the_timescale()
+{
+    let mut merged = VCD::from_str(TRACE, "top.reset").unwrap();
+    merged.merge(VCD::from_str(&TRACE.replace("#10", "#12"), "top.reset").unwrap()).unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(merged, &mut output, &WriteOptions { provenance : true, ..WriteOptions::default() }).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    // 2 ticks of 1 ns
+    assert!(output.contains("<string> : reset end at 10, timeskew 2 ns"), "{}", output);
+}
+
+#[test]
+fn string_changes_are_kept()
+{
+    let mut merged = VCD::from_str(STRINGS, "top.reset").unwrap();
+    merged.merge(VCD::from_str(STRINGS, "top.reset").unwrap()).unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(merged, &mut output, &WriteOptions::default()).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    // Strings start empty, which has no VCD spelling
+    assert!(output.contains("$dumpvars\n0!\n0#\n$end\n"), "{}", output);
+    assert!(output.contains("#20\nsbusy \"\nsbusy $\n"), "{}", output);
+}
+
+const PAUSED : &str = "$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 1 \" valid $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+#10
+1!
+#20
+$dumpoff
+x!
+x\"
+$end
+#30
+$dumpon
+1!
+1\"
+$end
+";
+
+#[test]
+fn dump_sections_only_hold_their_file()
+{
+    let mut merged = VCD::from_str(PAUSED, "top.reset").unwrap();
+    merged.merge(VCD::from_str(STRINGS, "top.reset").unwrap()).unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(merged, &mut output, &WriteOptions::default()).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("#20\nsbusy $\n$dumpoff\nx!\nx\"\n$end\n"), "{}", output);
+    assert!(output.contains("#30\n$dumpon\n1!\n1\"\n$end\n"), "{}", output);
+}
+
+#[test]
+fn dump_sections_are_written_without_changes()
+{
+    let mut vcd = VCD::from_str(PAUSED, "top.reset").unwrap();
+    // As if the changes there had been filtered away, $dumpon dumps the
+    // state from before the $dumpoff
+    vcd.values.remove(&20);
+    vcd.values.get_mut(&30).unwrap().retain(|(id, _)| *id == 0);
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(vcd, &mut output, &WriteOptions::default()).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("#10\n1!\n#20\n$dumpoff\nx!\nx\"\n$end\n#30\n$dumpon\n1!\n0\"\n$end\n"), "{}", output);
+}
+
+#[test]
+fn dump_sections_can_start_the_trace()
+{
+    let vcd = VCD::from_str("$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! reset $end
+$var wire 1 \" valid $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpoff
+x!
+x\"
+$end
+#10
+$dumpon
+0!
+1\"
+$end
+0\"
+#20
+1!
+", "top.reset").unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(vcd, &mut output, &WriteOptions::default()).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let body = &output[output.find("#0").unwrap()..];
+    // The change after $dumpon overrides the one in it and stays out of it
+    assert_eq!(body, "#0\n$dumpvars\nx!\nx\"\n$end\n$dumpoff\nx!\nx\"\n$end\n#10\n0\"\n$dumpon\n0!\n$end\n#20\n1!\n");
+}
+
+fn trace(name : &str, rst_end : u64) -> VCD
+{
+    let mut builder = common::reset_trace(name, "top", rst_end);
+    builder.add_signal("top.count", 4, VarType::Reg);
+    for step in 1..5u64
+    {
+        let count = format!("{:04b}", step).parse().unwrap();
+        builder.add_event(rst_end + 3 * step, "top.count", StoredValue::Vector(count)).unwrap();
+    }
+    builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap()
+}
+
+#[test]
+fn streamed_merge_matches_merge()
+{
+    let (a, b) = (trace("a", 10), trace("b", 25));
+    let mut merged = a.clone();
+    merged.merge(b.clone()).unwrap();
+    let mut expected = Vec::new();
+    write_vcd_to_writer(merged, &mut expected, &WriteOptions::default()).unwrap();
+
+    let mut streamed = Vec::new();
+    a.merge_stream(b, &mut streamed).unwrap();
+    assert_eq!(String::from_utf8(streamed).unwrap(), String::from_utf8(expected).unwrap());
+}
+
+#[test]
+fn preserved_id_codes_are_kept_unless_taken()
+{
+    let trace = |name : &str, data : &str|
+    {
+        VCD::from_str(&format!("$timescale 1 ns $end
+$scope module {} $end
+$var wire 1 a reset $end
+$var wire 1 {} data $end
+$upscope $end
+$enddefinitions $end
+#0
+0a
+#10
+1a
+", name, data), &format!("{}.reset", name)).unwrap()
+    };
+    let mut merged = trace("first", "b");
+    merged.merge(trace("second", "c")).unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(merged, &mut output, &WriteOptions { preserve_id_codes : true, ..WriteOptions::default() }).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    // The reset of the second file can't keep a, it gets the first free code
+    for var in ["$var wire 1 a reset $end", "$var wire 1 b data $end", "$var wire 1 ! reset $end", "$var wire 1 c data $end"]
+    {
+        assert!(output.contains(var), "{}", output);
+    }
+}
+
+#[test]
+fn close_timestamps_are_merged()
+{
+    let bit = |id, value| (id, StoredValue::Scalar(value));
+    let mut values = TimestampValues::new();
+    values.insert(0, vec![bit(0, Value::V0)]);
+    values.insert(1, vec![bit(0, Value::V1)]);
+    values.insert(2, vec![bit(1, Value::V1)]);
+    values.insert(10, vec![]);
+    values.insert(20, vec![bit(1, Value::V0)]);
+    values.insert(22, vec![bit(0, Value::V0)]);
+
+    prune_empty_timestamps(&mut values);
+    assert_eq!(values.keys().copied().collect::<Vec<_>>(), [0, 1, 2, 20, 22]);
+    // Groups are measured from their first timestamp, 2 is 2 ticks after 0
+    assert_eq!(merge_zero_duration_timestamps(&mut values, 2), 3);
+    assert_eq!(values.keys().copied().collect::<Vec<_>>(), [0, 20]);
+    assert_eq!(values[&0], [bit(0, Value::V0), bit(0, Value::V1), bit(1, Value::V1)]);
+}
+
+#[test]
+fn timestamps_can_be_commented()
+{
+    let vcd = VCD::from_str(TRACE, "top.reset").unwrap();
+
+    let mut output = Vec::new();
+    write_vcd_to_writer(vcd, &mut output, &WriteOptions { timestamp_comments : true, ..WriteOptions::default() }).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("#10\n$comment T=10 ns $end\n1!\n"), "{}", output);
+}
+
+#[test]
+fn sorted_outputs_ignore_declaration_order()
+{
+    let trace = |name : &str, rst_end : u64, signals : &[&str]|
+    {
+        let mut builder = VcdBuilder::new(name, 1, TimescaleUnit::NS);
+        for signal in signals
+        {
+            builder.add_signal(signal, 1, VarType::Wire);
+        }
+        for signal in signals
+        {
+            builder.add_event(rst_end + 2, signal, StoredValue::Scalar(Value::V1)).unwrap();
+        }
+        builder.add_event(0, "top.reset_n", StoredValue::Scalar(Value::V0)).unwrap();
+        builder.add_event(rst_end, "top.reset_n", StoredValue::Scalar(Value::V1)).unwrap();
+        builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap()
+    };
+    let options = WriteOptions { sort_signals : true, ..WriteOptions::default() };
+    let merge = |signals : &[&str], streamed : bool|
+    {
+        let (a, b) = (trace("a", 10, signals), trace("b", 25, &["top.reset_n", "top.b.ready"]));
+        let mut output = Vec::new();
+        match streamed
+        {
+            true => a.merge_stream_with(b, 1, |_| Value::V0, &mut output, &options).unwrap(),
+            false =>
+            {
+                let mut merged = a;
+                merged.merge(b).unwrap();
+                write_vcd_to_writer(merged, &mut output, &options).unwrap();
+            },
+        }
+        String::from_utf8(output).unwrap()
+    };
+
+    let sorted = merge(&["top.reset_n", "top.valid", "top.a.data"], false);
+    assert_eq!(merge(&["top.a.data", "top.valid", "top.reset_n"], false), sorted);
+    assert_eq!(merge(&["top.valid", "top.reset_n", "top.a.data"], true), sorted);
+    let vars : Vec<_> = sorted.lines().filter(|line| line.starts_with("$var")).collect();
+    assert_eq!(vars, ["$var wire 1 ! reset_n $end", "$var wire 1 \" reset_n_2 $end", "$var wire 1 # valid $end",
+                      "$var wire 1 $ data $end", "$var wire 1 % ready $end"]);
+}
+
+#[test]
+fn csv_string_values_are_quoted()
+{
+    let mut builder = VcdBuilder::new("labels", 1, TimescaleUnit::NS);
+    builder.add_signal("top.reset_n", 1, VarType::Wire).add_signal("top.lbl", 0, VarType::String);
+    builder.add_event(0, "top.reset_n", StoredValue::Scalar(Value::V0)).unwrap();
+    builder.add_event(10, "top.reset_n", StoredValue::Scalar(Value::V1)).unwrap();
+    builder.add_event(10, "top.lbl", StoredValue::Str("a,b".to_string())).unwrap();
+    builder.add_event(20, "top.lbl", StoredValue::Str("say\"hi\"".to_string())).unwrap();
+    let vcd = builder.build("top.reset_n", ResetPolarity::ActiveLow).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("vcd_sync_csv_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("trace.csv");
+    write_csv(vcd, &path, &WriteOptions::default()).unwrap();
+    let csv = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(csv.contains("\n10,top.lbl,\"a,b\"\n"), "{}", csv);
+    assert!(csv.contains("\n20,top.lbl,\"say\"\"hi\"\"\"\n"), "{}", csv);
+}
+
+#[test]
+fn fst_headers_keep_the_input_metadata()
+{
+    let vcd = VCD::from_str(&format!("$date Mon Oct 12 2026 $end\n$version Icarus 12 $end\n{}", TRACE), "top.reset").unwrap();
+
+    let dir = std::env::temp_dir().join(format!("vcd_sync_fst_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("trace.fst");
+    write_fst(vcd, &path, &WriteOptions::default()).unwrap();
+    let fst = fs::read(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let contains = |text : &str| fst.windows(text.len()).any(|window| window == text.as_bytes());
+    assert!(contains("Icarus 12"));
+    assert!(contains("Mon Oct 12 2026"));
+}